@@ -0,0 +1,405 @@
+//! Keyframe animation of anything numeric in a scene.
+//!
+//! Tracks point at a value of the scene the same way diagnostics do, e.g.
+//! `elements[0].Sphere.center` or `lights[1].Spherical.intensity`, and list keyframes in
+//! seconds. Numbers are interpolated, and so are maps and lists of numbers like points,
+//! colors or whole materials, component by component. Anything else switches at the key.
+//!
+//! Frames are made by setting every track to its value at the frame's time in the scene
+//! file's value tree, before the tree becomes a `Scene`.
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// Timing curve through (0, 0), (x1, y1), (x2, y2) and (1, 1) like CSS `cubic-bezier`,
+    /// `[0.42, 0.0, 0.58, 1.0]` eases in and out
+    Bezier([f64; 4]),
+}
+
+impl Interpolation {
+    /// Maps the linear progress `u` in [0, 1] between two keys onto the curve
+    pub fn ease(self, u: f64) -> f64 {
+        match self {
+            Interpolation::Linear => u,
+            Interpolation::Bezier([x1, y1, x2, y2]) => {
+                let bezier = |a: f64, b: f64, s: f64| {
+                    3.0 * a * s * (1.0 - s) * (1.0 - s) + 3.0 * b * s * s * (1.0 - s) + s * s * s
+                };
+                // x(s) is monotonic for handles in [0, 1], bisect for the s giving x = u
+                let (mut low, mut high) = (0.0, 1.0);
+                for _ in 0..40 {
+                    let mid = (low + high) / 2.0;
+                    if bezier(x1, x2, mid) < u {
+                        low = mid;
+                    } else {
+                        high = mid;
+                    }
+                }
+                bezier(y1, y2, (low + high) / 2.0)
+            }
+        }
+    }
+}
+
+/// Where a time falls between the keys: `to` is `from + 1` except before the first
+/// and after the last key, where both are the same key
+pub struct Segment {
+    pub from: usize,
+    pub to: usize,
+    /// Eased progress from `from` to `to`
+    pub u: f64,
+}
+
+/// Finds the keys around `time`, `times` has to be sorted
+pub fn segment(times: &[f64], interpolations: &[Interpolation], time: f64) -> Segment {
+    let last = times.len().saturating_sub(1);
+    let to = times.iter().position(|t| *t > time).unwrap_or(last + 1);
+    if to == 0 || to > last {
+        let key = to.min(last);
+        return Segment {
+            from: key,
+            to: key,
+            u: 0.0,
+        };
+    }
+    let from = to - 1;
+    let u = (time - times[from]) / (times[to] - times[from]);
+    Segment {
+        from,
+        to,
+        u: interpolations[from].ease(u),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Keyframe {
+    /// Seconds from the start of the animation
+    pub time: f64,
+    pub value: Value,
+    /// How to get from this key to the next one
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Track {
+    /// What to animate, e.g. `camera.position` or `elements[2].Plane.material.albedo`
+    pub path: String,
+    pub keys: Vec<Keyframe>,
+}
+
+/// Scene fields that only take whole numbers. Animated values for them are rounded,
+/// everything else is interpolated as a fraction even between whole-number keys.
+const INTEGER_FIELDS: [&str; 9] = [
+    "width",
+    "height",
+    "max_recursion_depth",
+    "max_bounces",
+    "max_steps",
+    "samples",
+    "min_samples",
+    "iterations",
+    "seed",
+];
+
+impl Track {
+    pub fn value_at(&self, time: f64) -> Value {
+        let times: Vec<f64> = self.keys.iter().map(|k| k.time).collect();
+        let interpolations: Vec<Interpolation> =
+            self.keys.iter().map(|k| k.interpolation).collect();
+        let s = segment(&times, &interpolations, time);
+        let field = self.path.rsplit('.').next().unwrap_or_default();
+        let field = field.split('[').next().unwrap_or_default();
+        lerp(
+            &self.keys[s.from].value,
+            &self.keys[s.to].value,
+            s.u,
+            INTEGER_FIELDS.contains(&field),
+        )
+    }
+}
+
+fn lerp(a: &Value, b: &Value, u: f64, integer: bool) -> Value {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => {
+            let (x, y) = (
+                x.as_f64().unwrap_or_default(),
+                y.as_f64().unwrap_or_default(),
+            );
+            let v = x + (y - x) * u;
+            if integer {
+                Value::from(v.round() as i64)
+            } else {
+                Number::from_f64(v).map_or(a.clone(), Value::Number)
+            }
+        }
+        (Value::Object(x), Value::Object(y)) => Value::Object(
+            x.iter()
+                .map(|(key, v)| {
+                    let integer = INTEGER_FIELDS.contains(&key.as_str());
+                    let v = y.get(key).map_or(v.clone(), |w| lerp(v, w, u, integer));
+                    (key.clone(), v)
+                })
+                .collect(),
+        ),
+        (Value::Array(x), Value::Array(y)) if x.len() == y.len() => Value::Array(
+            x.iter()
+                .zip(y)
+                .map(|(v, w)| lerp(v, w, u, integer))
+                .collect(),
+        ),
+        _ if u < 1.0 => a.clone(),
+        _ => b.clone(),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Animation {
+    #[serde(default = "Animation::default_fps")]
+    pub fps: f64,
+    /// First and last frame, both included. Defaults to the frames up to the last key.
+    #[serde(default)]
+    pub frames: Option<(u32, u32)>,
+    pub tracks: Vec<Track>,
+}
+
+impl Animation {
+    fn default_fps() -> f64 {
+        24.0
+    }
+
+    /// Seconds until the last key
+    pub fn duration(&self) -> f64 {
+        self.tracks
+            .iter()
+            .flat_map(|t| t.keys.iter().map(|k| k.time))
+            .fold(0.0, f64::max)
+    }
+
+    pub fn frame_range(&self, fps: f64) -> (u32, u32) {
+        self.frames
+            .unwrap_or((0, (self.duration() * fps).ceil() as u32))
+    }
+
+    /// Checks the tracks, the errors point into the scene file like diagnostics do
+    pub fn check(&self, scene: &Value) -> Result<(), String> {
+        if self.fps.is_nan() || self.fps <= 0.0 {
+            return Err(format!(
+                "animation.fps: has to be positive, got {}",
+                self.fps
+            ));
+        }
+        for (i, track) in self.tracks.iter().enumerate() {
+            let at = |what: &str| format!("animation.tracks[{}].{}", i, what);
+            if track.keys.is_empty() {
+                return Err(format!("{}: a track needs at least one key", at("keys")));
+            }
+            if track.keys.windows(2).any(|w| w[0].time >= w[1].time) {
+                return Err(format!("{}: keys have to be in time order", at("keys")));
+            }
+            let mut scene = scene.clone();
+            target(&mut scene, &track.path).map_err(|err| format!("{}: {}", at("path"), err))?;
+        }
+        Ok(())
+    }
+
+    /// Sets every track to its value at `time`
+    pub fn apply(&self, scene: &mut Value, time: f64) -> Result<(), String> {
+        for track in &self.tracks {
+            *target(scene, &track.path)? = track.value_at(time);
+        }
+        Ok(())
+    }
+}
+
+/// The value a path like `elements[0].Sphere.center` points at.
+/// Missing map keys are added, so `camera.position` works without a camera in the file.
+fn target<'a>(scene: &'a mut Value, path: &str) -> Result<&'a mut Value, String> {
+    let mut value = scene;
+    for part in path.split('.') {
+        let (key, indices) = match part.find('[') {
+            Some(i) => (&part[..i], &part[i..]),
+            None => (part, ""),
+        };
+        if !key.is_empty() {
+            if value.is_null() {
+                *value = Value::Object(Map::new());
+            }
+            value = value
+                .as_object_mut()
+                .ok_or_else(|| format!("'{}' in {} isn't inside a map", key, path))?
+                .entry(key)
+                .or_insert(Value::Null);
+        }
+        for index in indices.split('[').skip(1) {
+            let index: usize = index
+                .strip_suffix(']')
+                .and_then(|i| i.parse().ok())
+                .ok_or_else(|| format!("bad index '[{}' in {}", index, path))?;
+            value = value
+                .get_mut(index)
+                .ok_or_else(|| format!("no element {} in {}", index, path))?;
+        }
+    }
+    Ok(value)
+}
+
+/// `output/test_scene.png` => `output/test_scene_0007.png`
+pub fn frame_path(output: &Path, frame: u32) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = output.extension().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!("{}_{:04}.{}", stem, frame, extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const EASE_IN_OUT: Interpolation = Interpolation::Bezier([0.42, 0.0, 0.58, 1.0]);
+
+    fn track(path: &str, keys: &[(f64, Value, Interpolation)]) -> Track {
+        Track {
+            path: path.to_string(),
+            keys: keys
+                .iter()
+                .map(|(time, value, interpolation)| Keyframe {
+                    time: *time,
+                    value: value.clone(),
+                    interpolation: *interpolation,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn whole_numbers_interpolate_as_fractions() {
+        assert_eq!(lerp(&json!(1), &json!(2), 0.5, false), json!(1.5));
+        assert_eq!(lerp(&json!(1.0), &json!(3), 0.25, false), json!(1.5));
+    }
+
+    #[test]
+    fn integer_fields_round() {
+        assert_eq!(lerp(&json!(320), &json!(641), 0.5, true), json!(481));
+        let v = lerp(
+            &json!({"width": 100, "fov": 60}),
+            &json!({"width": 201, "fov": 91}),
+            0.5,
+            false,
+        );
+        assert_eq!(v, json!({"width": 151, "fov": 75.5}));
+        let width = track(
+            "width",
+            &[
+                (0.0, json!(100), Interpolation::Linear),
+                (1.0, json!(200), Interpolation::Linear),
+            ],
+        );
+        assert_eq!(width.value_at(0.333), json!(133));
+    }
+
+    #[test]
+    fn lists_interpolate_per_component_and_the_rest_switches() {
+        assert_eq!(
+            lerp(&json!([0, 0, 0]), &json!([2, 4, -2]), 0.5, false),
+            json!([1.0, 2.0, -1.0])
+        );
+        assert_eq!(lerp(&json!("a"), &json!("b"), 0.5, false), json!("a"));
+        assert_eq!(lerp(&json!("a"), &json!("b"), 1.0, false), json!("b"));
+    }
+
+    #[test]
+    fn ease() {
+        for u in [0.0, 0.3, 1.0] {
+            assert_eq!(Interpolation::Linear.ease(u), u);
+        }
+        assert!(EASE_IN_OUT.ease(0.0).abs() < 1e-9);
+        assert!((EASE_IN_OUT.ease(1.0) - 1.0).abs() < 1e-9);
+        assert!((EASE_IN_OUT.ease(0.5) - 0.5).abs() < 1e-9);
+        assert!(EASE_IN_OUT.ease(0.2) < 0.2);
+        assert!(EASE_IN_OUT.ease(0.8) > 0.8);
+        let mut last = 0.0;
+        for i in 1..=10 {
+            let eased = EASE_IN_OUT.ease(i as f64 / 10.0);
+            assert!(eased >= last);
+            last = eased;
+        }
+    }
+
+    #[test]
+    fn segments() {
+        let times = [1.0, 2.0, 4.0];
+        let linear = [Interpolation::Linear; 3];
+        let before = segment(&times, &linear, 0.0);
+        assert_eq!((before.from, before.to, before.u), (0, 0, 0.0));
+        let after = segment(&times, &linear, 5.0);
+        assert_eq!((after.from, after.to, after.u), (2, 2, 0.0));
+        let between = segment(&times, &linear, 3.0);
+        assert_eq!((between.from, between.to, between.u), (1, 2, 0.5));
+        let on_key = segment(&times, &linear, 2.0);
+        assert_eq!((on_key.from, on_key.to, on_key.u), (1, 2, 0.0));
+        let eased = segment(
+            &times,
+            &[Interpolation::Linear, EASE_IN_OUT, EASE_IN_OUT],
+            2.5,
+        );
+        assert!(eased.u < 0.25);
+    }
+
+    #[test]
+    fn keyframes() {
+        let linear = track(
+            "lights[0].Spherical.intensity",
+            &[
+                (0.0, json!(1), Interpolation::Linear),
+                (2.0, json!(3), Interpolation::Linear),
+            ],
+        );
+        assert_eq!(linear.value_at(-1.0), json!(1.0));
+        assert_eq!(linear.value_at(1.0), json!(2.0));
+        assert_eq!(linear.value_at(3.0), json!(3.0));
+        let eased = track(
+            "lights[0].Spherical.intensity",
+            &[
+                (0.0, json!(0.0), EASE_IN_OUT),
+                (1.0, json!(1.0), Interpolation::Linear),
+            ],
+        );
+        let early = eased.value_at(0.2).as_f64().unwrap();
+        assert!(early > 0.0 && early < 0.2);
+    }
+
+    #[test]
+    fn apply_sets_the_tracks() {
+        let animation = Animation {
+            fps: 24.0,
+            frames: None,
+            tracks: vec![track(
+                "elements[0].Sphere.center",
+                &[
+                    (0.0, json!({"x": 0, "y": 0, "z": -5}), Interpolation::Linear),
+                    (1.0, json!({"x": 2, "y": 0, "z": -5}), Interpolation::Linear),
+                ],
+            )],
+        };
+        let mut scene = json!({"elements": [{"Sphere": {"radius": 1.0}}]});
+        animation.check(&scene).unwrap();
+        animation.apply(&mut scene, 0.5).unwrap();
+        assert_eq!(scene["elements"][0]["Sphere"]["center"]["x"], json!(1.0));
+        assert_eq!(animation.frame_range(24.0), (0, 24));
+        assert!(animation.check(&json!({"elements": []})).is_err());
+    }
+
+    #[test]
+    fn frame_paths() {
+        assert_eq!(
+            frame_path(Path::new("output/test_scene.png"), 7),
+            Path::new("output/test_scene_0007.png")
+        );
+    }
+}
@@ -0,0 +1,239 @@
+//! Fluent construction of scenes in code, for tests and tools that generate scenes.
+//!
+//! ```ignore
+//! let scene = Scene::builder()
+//!     .size(320, 240)
+//!     .sphere(Point::new(0.0, 0.0, -5.0), 1.0, Material::diffuse(Color::new(1.0, 0.2, 0.2)))
+//!     .light(Light::directional(Vector3::new(0.0, -1.0, -1.0), Color::new(1.0, 1.0, 1.0), 3.0))
+//!     .build();
+//! ```
+use crate::{
+    camera::Camera,
+    conductor::Conductor,
+    entities::{
+        Color, Coloration, DirectionalLight, Element, Emission, Light, Material, Plane, Sphere,
+        SphericalLight, SpotLight, SurfaceType,
+    },
+    point::Point,
+    scene::{Effects, Scene},
+    sky::Sky,
+    spectral::RefractiveIndex,
+    vector::Vector3,
+    volume::Fog,
+};
+
+pub struct SceneBuilder {
+    scene: Scene,
+}
+
+impl Scene {
+    /// 800x600, 90° field of view and nothing in it
+    pub fn builder() -> SceneBuilder {
+        SceneBuilder {
+            scene: Scene {
+                width: 800,
+                height: 600,
+                fov: 90.0,
+                camera: Camera::default(),
+                time: 0.0,
+                effects: Effects::default(),
+                elements: vec![],
+                lights: vec![],
+                shadow_bias: 1e-13,
+                max_recursion_depth: 10,
+                passes: vec![],
+                denoise: None,
+                progressive: None,
+                path_tracing: None,
+                sky: None,
+                fog: None,
+                volumes: None,
+                volume_grids: vec![],
+                spectral: None,
+                stats: Default::default(),
+            },
+        }
+    }
+}
+
+impl SceneBuilder {
+    pub fn size(mut self, width: u32, height: u32) -> SceneBuilder {
+        self.scene.width = width;
+        self.scene.height = height;
+        self
+    }
+
+    pub fn fov(mut self, fov: f64) -> SceneBuilder {
+        self.scene.fov = fov;
+        self
+    }
+
+    pub fn camera(mut self, camera: Camera) -> SceneBuilder {
+        self.scene.camera = camera;
+        self
+    }
+
+    pub fn effects(mut self, effects: Effects) -> SceneBuilder {
+        self.scene.effects = effects;
+        self
+    }
+
+    pub fn time(mut self, time: f64) -> SceneBuilder {
+        self.scene.time = time;
+        self
+    }
+
+    pub fn shadow_bias(mut self, shadow_bias: f64) -> SceneBuilder {
+        self.scene.shadow_bias = shadow_bias;
+        self
+    }
+
+    pub fn max_recursion_depth(mut self, depth: u32) -> SceneBuilder {
+        self.scene.max_recursion_depth = depth;
+        self
+    }
+
+    pub fn element(mut self, element: Element) -> SceneBuilder {
+        self.scene.elements.push(element);
+        self
+    }
+
+    pub fn sphere(self, center: Point, radius: f64, material: Material) -> SceneBuilder {
+        self.element(Element::Sphere(Sphere {
+            center,
+            radius,
+            material,
+            motion: None,
+        }))
+    }
+
+    pub fn plane(self, origin: Point, normal: Vector3, material: Material) -> SceneBuilder {
+        self.element(Element::Plane(Plane {
+            origin,
+            normal: normal.normalize(),
+            material,
+            motion: None,
+        }))
+    }
+
+    pub fn sky(mut self, sky: Sky) -> SceneBuilder {
+        self.scene.sky = Some(sky);
+        self
+    }
+
+    pub fn fog(mut self, fog: Fog) -> SceneBuilder {
+        self.scene.fog = Some(fog);
+        self
+    }
+
+    pub fn light(mut self, light: Light) -> SceneBuilder {
+        self.scene.lights.push(light);
+        self
+    }
+
+    pub fn build(self) -> Scene {
+        self.scene
+    }
+}
+
+impl Material {
+    pub fn diffuse(color: Color) -> Material {
+        Material {
+            coloration: Coloration::Color(color),
+            albedo: 0.18,
+            surface: SurfaceType::Diffuse,
+            emission: None,
+            medium: None,
+            relief: None,
+            cutout: None,
+        }
+    }
+
+    /// Diffuse material glowing with `color` times `strength`
+    pub fn emissive(color: Color, strength: f32) -> Material {
+        Material {
+            emission: Some(Emission {
+                coloration: Coloration::Color(color),
+                strength,
+            }),
+            ..Material::diffuse(color)
+        }
+    }
+
+    pub fn reflective(color: Color, reflectivity: f32) -> Material {
+        Material {
+            surface: SurfaceType::Reflective { reflectivity },
+            ..Material::diffuse(color)
+        }
+    }
+
+    /// Coloration is the metal's color straight on, for the albedo pass and when
+    /// reflections are off
+    pub fn conductor(metal: Conductor) -> Material {
+        Material {
+            surface: SurfaceType::Conductor(metal),
+            ..Material::diffuse(metal.color())
+        }
+    }
+
+    pub fn refractive(color: Color, index: f32, transparency: f32) -> Material {
+        Material {
+            surface: SurfaceType::Refractive {
+                index: RefractiveIndex::Constant(index),
+                transparency,
+            },
+            ..Material::diffuse(color)
+        }
+    }
+}
+
+impl Light {
+    pub fn directional(direction: Vector3, color: Color, intensity: f32) -> Light {
+        Light::Directional(DirectionalLight {
+            direction: direction.normalize(),
+            color,
+            intensity,
+        })
+    }
+
+    pub fn spherical(position: Point, color: Color, intensity: f32) -> Light {
+        Light::Spherical(SphericalLight {
+            position,
+            color,
+            intensity,
+        })
+    }
+
+    /// Spot light without a gobo, cone angles in degrees from the axis
+    pub fn spot(
+        position: Point,
+        direction: Vector3,
+        color: Color,
+        intensity: f32,
+        inner_angle: f64,
+        outer_angle: f64,
+    ) -> Light {
+        Light::Spot(SpotLight {
+            position,
+            direction: direction.normalize(),
+            color,
+            intensity,
+            inner_angle,
+            outer_angle,
+            gobo: None,
+        })
+    }
+}
+
+/// The scene tests start from: a unit sphere of `material` 5 units in front of the camera,
+/// lit from straight above by a white directional light
+#[cfg(test)]
+pub fn lit_sphere(material: Material) -> SceneBuilder {
+    Scene::builder()
+        .sphere(Point::new(0.0, 0.0, -5.0), 1.0, material)
+        .light(Light::directional(
+            Vector3::new(0.0, -1.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            1.0,
+        ))
+}
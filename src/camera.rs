@@ -0,0 +1,95 @@
+//! Pinhole camera. Without one in the scene file it sits in the origin looking down -z,
+//! which is where the renderer always looked from.
+use crate::{point::Point, vector::Vector3};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct Camera {
+    pub position: Point,
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub direction: Vector3,
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub up: Vector3,
+    /// When the shutter opens and closes in seconds from the scene's time. Elements with
+    /// a `motion` are blurred over that interval, progressive samples spread over it.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub shutter_open: f64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub shutter_close: f64,
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera {
+            position: Point::zero(),
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            up: Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+}
+
+impl Camera {
+    /// Orthonormal right, up and forward vectors
+    pub fn basis(&self) -> (Vector3, Vector3, Vector3) {
+        let forward = self.direction.normalize();
+        let right = forward.cross(&self.up).normalize();
+        let up = right.cross(&forward);
+        (right, up, forward)
+    }
+
+    /// Seconds from the scene's time to the moment `shutter` in [0, 1] of the exposure
+    pub fn shutter_time(&self, shutter: f64) -> f64 {
+        self.shutter_open + (self.shutter_close - self.shutter_open) * shutter
+    }
+
+    /// Whether the exposure takes any time at all
+    pub fn has_exposure(&self) -> bool {
+        self.shutter_close != self.shutter_open
+    }
+
+    /// World space direction through a point of the sensor, both coordinates in [-1, 1] scaled by fov
+    pub fn direction_through(&self, sensor_x: f64, sensor_y: f64) -> Vector3 {
+        let (right, up, forward) = self.basis();
+        (right * sensor_x + up * sensor_y + forward).normalize()
+    }
+
+    /// Moves along the view direction, to the right and up
+    pub fn translate(&mut self, forward: f64, right: f64, up: f64) {
+        let (r, u, f) = self.basis();
+        self.position = self.position + f * forward + r * right + u * up;
+    }
+
+    /// Swings the camera around `pivot`, yaw around the world up axis and pitch around the camera's right
+    pub fn orbit(&mut self, pivot: Point, yaw: f64, pitch: f64) {
+        let (right, _, _) = self.basis();
+        let offset = rotate(rotate(self.position - pivot, self.up, yaw), right, pitch);
+        // don't flip over the poles
+        let new_direction = (-offset).normalize();
+        if new_direction.cross(&self.up).length() < 0.05 {
+            return;
+        }
+        self.position = pivot + offset;
+        self.direction = new_direction;
+    }
+}
+
+/// Rodrigues' rotation of `v` around the unit `axis`
+fn rotate(v: Vector3, axis: Vector3, angle: f64) -> Vector3 {
+    let (sin, cos) = angle.sin_cos();
+    v * cos + axis.cross(&v) * sin + axis * (axis.dot(&v) * (1.0 - cos))
+}
+
+fn is_zero(seconds: &f64) -> bool {
+    *seconds == 0.0
+}
@@ -0,0 +1,283 @@
+//! Snapshots of a progressive render, so a killed process doesn't lose hours of samples.
+//!
+//! The file is a small little-endian binary dump: a header with the scene hash, image size,
+//! pass count and sampler state, followed by the accumulation buffers pixel by pixel.
+use crate::{
+    entities::Color,
+    passes::PassSample,
+    progressive::{Accumulator, ProgressiveRenderer},
+    sampling::Rng,
+    vector::Vector3,
+};
+
+use std::{
+    fmt, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 1;
+
+/// FNV-1a, stable between runs and Rust versions unlike `DefaultHasher`
+pub fn scene_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// `output/test_scene.png` => `output/test_scene.checkpoint`
+pub fn checkpoint_path(output: &Path) -> PathBuf {
+    output.with_extension("checkpoint")
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    NotACheckpoint,
+    SceneChanged,
+    SizeChanged,
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "{}", err),
+            CheckpointError::NotACheckpoint => write!(f, "not a checkpoint file"),
+            CheckpointError::SceneChanged => {
+                write!(f, "the scene changed since the checkpoint was written")
+            }
+            CheckpointError::SizeChanged => {
+                write!(f, "the image size changed since the checkpoint was written")
+            }
+        }
+    }
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(err: io::Error) -> CheckpointError {
+        CheckpointError::Io(err)
+    }
+}
+
+pub struct Checkpoint {
+    pub scene_hash: u64,
+    pub pass: u32,
+    pub rng: Rng,
+    pub accumulator: Accumulator,
+}
+
+impl Checkpoint {
+    /// Snapshots the renderer state.
+    /// Writes next to the target first, so a kill in the middle never leaves a broken checkpoint.
+    pub fn save(renderer: &ProgressiveRenderer, scene_hash: u64, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("checkpoint.tmp");
+        let mut out = io::BufWriter::new(fs::File::create(&tmp)?);
+        let acc = &renderer.accumulator;
+
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&scene_hash.to_le_bytes())?;
+        out.write_all(&acc.width.to_le_bytes())?;
+        out.write_all(&acc.height.to_le_bytes())?;
+        out.write_all(&renderer.pass.to_le_bytes())?;
+        out.write_all(&renderer.rng.state().to_le_bytes())?;
+
+        for i in 0..acc.counts.len() {
+            let (x, y) = (i as u32 % acc.width, i as u32 / acc.width);
+            let guide = acc.guides.get(x, y);
+            write_color(&mut out, acc.direct[i])?;
+            write_color(&mut out, acc.indirect[i])?;
+            out.write_all(&acc.luminance2[i].to_le_bytes())?;
+            out.write_all(&acc.counts[i].to_le_bytes())?;
+            out.write_all(&guide.depth.to_le_bytes())?;
+            for v in &[guide.normal.x, guide.normal.y, guide.normal.z] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+            write_color(&mut out, guide.albedo)?;
+            out.write_all(&guide.shadow.to_le_bytes())?;
+            out.write_all(&guide.object_id.to_le_bytes())?;
+        }
+        out.flush()?;
+        drop(out);
+        fs::rename(tmp, path)
+    }
+
+    /// Loads a checkpoint, refusing it if it belongs to a different scene
+    pub fn load(
+        path: &Path,
+        scene_hash: u64,
+        width: u32,
+        height: u32,
+    ) -> Result<Checkpoint, CheckpointError> {
+        let mut input = io::BufReader::new(fs::File::open(path)?);
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u32(&mut input)? != VERSION {
+            return Err(CheckpointError::NotACheckpoint);
+        }
+        if read_u64(&mut input)? != scene_hash {
+            return Err(CheckpointError::SceneChanged);
+        }
+        if read_u32(&mut input)? != width || read_u32(&mut input)? != height {
+            return Err(CheckpointError::SizeChanged);
+        }
+        let pass = read_u32(&mut input)?;
+        let rng = Rng::from_state(read_u64(&mut input)?);
+
+        let mut acc = Accumulator::new(width, height);
+        for i in 0..acc.counts.len() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            acc.direct[i] = read_color(&mut input)?;
+            acc.indirect[i] = read_color(&mut input)?;
+            acc.luminance2[i] = read_f32(&mut input)?;
+            acc.counts[i] = read_u32(&mut input)?;
+            let depth = read_f32(&mut input)?;
+            let normal = Vector3 {
+                x: read_f64(&mut input)?,
+                y: read_f64(&mut input)?,
+                z: read_f64(&mut input)?,
+            };
+            let albedo = read_color(&mut input)?;
+            let shadow = read_f32(&mut input)?;
+            let object_id = read_u32(&mut input)?;
+            acc.guides.set(
+                x,
+                y,
+                PassSample {
+                    depth,
+                    normal,
+                    albedo,
+                    shadow,
+                    object_id,
+                    ..PassSample::default()
+                },
+            );
+        }
+
+        Ok(Checkpoint {
+            scene_hash,
+            pass,
+            rng,
+            accumulator: acc,
+        })
+    }
+}
+
+fn write_color<W: Write>(out: &mut W, c: Color) -> io::Result<()> {
+    for v in &[c.red, c.green, c.blue] {
+        out.write_all(&v.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_color<R: Read>(input: &mut R) -> io::Result<Color> {
+    Ok(Color::new(
+        read_f32(input)?,
+        read_f32(input)?,
+        read_f32(input)?,
+    ))
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_f32<R: Read>(input: &mut R) -> io::Result<f32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
+
+fn read_f64<R: Read>(input: &mut R) -> io::Result<f64> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::lit_sphere, entities::Material, progressive::Progressive, scene::Scene,
+        stats::Progress,
+    };
+
+    fn scene() -> Scene {
+        lit_sphere(Material::diffuse(Color::new(1.0, 0.2, 0.2)))
+            .size(4, 3)
+            .build()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "rtracer-{}-{}.checkpoint",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn rendered(scene: &Scene) -> ProgressiveRenderer<'_> {
+        let settings = Progressive {
+            samples: Some(3),
+            ..Progressive::default()
+        };
+        let mut renderer = ProgressiveRenderer::new(scene, settings, &[]);
+        renderer.run(&mut Progress::hidden(), |_| {});
+        renderer
+    }
+
+    #[test]
+    fn round_trip() {
+        let scene = scene();
+        let renderer = rendered(&scene);
+        let path = temp_path("round-trip");
+        Checkpoint::save(&renderer, 42, &path).unwrap();
+        assert!(!path.with_extension("checkpoint.tmp").exists());
+
+        let loaded = Checkpoint::load(&path, 42, 4, 3).unwrap();
+        fs::remove_file(&path).unwrap();
+        let (saved, acc) = (&renderer.accumulator, &loaded.accumulator);
+        assert_eq!(loaded.pass, renderer.pass);
+        assert_eq!(loaded.rng.state(), renderer.rng.state());
+        assert_eq!(acc.counts, saved.counts);
+        assert_eq!(acc.direct, saved.direct);
+        assert_eq!(acc.indirect, saved.indirect);
+        assert_eq!(acc.luminance2, saved.luminance2);
+        for y in 0..3 {
+            for x in 0..4 {
+                let (a, b) = (acc.guides.get(x, y), saved.guides.get(x, y));
+                assert_eq!(a.depth, b.depth);
+                assert_eq!(a.normal.x, b.normal.x);
+                assert_eq!(a.albedo, b.albedo);
+                assert_eq!(a.object_id, b.object_id);
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_other_scenes() {
+        let scene = scene();
+        let renderer = rendered(&scene);
+        let path = temp_path("mismatch");
+        Checkpoint::save(&renderer, 42, &path).unwrap();
+
+        let scene_changed = Checkpoint::load(&path, 43, 4, 3);
+        let size_changed = Checkpoint::load(&path, 42, 4, 4);
+        fs::write(&path, b"not a checkpoint at all").unwrap();
+        let garbage = Checkpoint::load(&path, 42, 4, 3);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(scene_changed, Err(CheckpointError::SceneChanged)));
+        assert!(matches!(size_changed, Err(CheckpointError::SizeChanged)));
+        assert!(matches!(garbage, Err(CheckpointError::NotACheckpoint)));
+    }
+}
@@ -0,0 +1,287 @@
+//! Tiny command line parser, we don't need much more than a couple of flags.
+use crate::{
+    denoise::Denoiser,
+    passes::{Pass, PassFormat, ALL_PASSES},
+    progressive::Progressive,
+};
+
+use serde_json::{Map, Value};
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "usage: rtracer-rs [COMMAND] [SCENE] [OPTIONS]
+
+scenes can be json, toml, yaml or ron files
+
+commands:
+    render                     render the scene to a file [default]
+    preview                    render the scene right in the terminal, q to quit
+    explore                    fly around the scene in the terminal, views are saved next to --output
+    animate                    render the frames of the scene's animation, numbered like output/test_scene_0001.png
+    watch                      render again whenever the scene or its textures change
+    check                      report suspicious values in the scene without rendering
+    convert <SCENE> <OUTPUT>   rewrite the scene as json, toml, yaml or ron, picked by the extension
+
+options:
+    -o, --output <PATH>        where to save the image [default: output/test_scene.png]
+    --set <NAME=VALUE>         override a variable of the scene, can be repeated
+    --frames <START..END>      frames to render, both included [default: up to the last keyframe]
+    --fps <N>                  frames per second of the animation [default: from the scene or 24]
+    --passes <LIST>            comma separated render passes to save next to the image:
+                               depth,normal,albedo,direct,indirect,shadow,id or all
+    --pass-format <png|exr>    separate png files or a single multi-layer exr [default: png]
+    --denoise                  filter the image with the edge-aware denoiser
+    --denoise-strength <0..1>  blend between the noisy and the filtered image [default: 1]
+    --denoise-compare          also save the noisy and denoised images side by side
+    --samples <N>              accumulate N jittered samples per pixel
+    --time-limit <SECONDS>     keep adding samples until the time runs out
+    --noise-threshold <F>      stop sampling pixels whose relative error is below F
+    --flush-interval <SECONDS> how often the current estimate is written [default: 10]
+    --seed <N>                 random seed for the sampler [default: 0]
+    --checkpoint <PATH>        where progressive renders are checkpointed
+                               [default: next to the output, e.g. output/test_scene.checkpoint]
+    --resume                   continue a progressive render from its checkpoint
+    --stats-json <PATH>        save the render statistics as json
+    -q, --quiet                no progress line and statistics
+    -h, --help                 print this message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Render,
+    Preview,
+    Explore,
+    Watch,
+    Convert,
+    Check,
+    Animate,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "render" => Some(Command::Render),
+            "preview" => Some(Command::Preview),
+            "explore" => Some(Command::Explore),
+            "watch" => Some(Command::Watch),
+            "convert" => Some(Command::Convert),
+            "check" => Some(Command::Check),
+            "animate" => Some(Command::Animate),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Args {
+    pub command: Command,
+    pub scene: PathBuf,
+    pub output: PathBuf,
+    /// Overrides of the scene's variables
+    pub variables: Map<String, Value>,
+    pub frames: Option<(u32, u32)>,
+    pub fps: Option<f64>,
+    /// Passes requested on the command line, added to the ones from the scene
+    pub passes: Vec<Pass>,
+    pub pass_format: PassFormat,
+    pub denoise: bool,
+    pub denoise_strength: Option<f32>,
+    pub denoise_compare: bool,
+    pub samples: Option<u32>,
+    pub time_limit: Option<f64>,
+    pub noise_threshold: Option<f32>,
+    pub flush_interval: Option<f64>,
+    pub seed: Option<u64>,
+    pub checkpoint: Option<PathBuf>,
+    pub resume: bool,
+    pub stats_json: Option<PathBuf>,
+    pub quiet: bool,
+    pub help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Args {
+        Args {
+            command: Command::Render,
+            scene: PathBuf::from("scenes/main.json"),
+            output: PathBuf::from("output/test_scene.png"),
+            variables: Map::new(),
+            frames: None,
+            fps: None,
+            passes: vec![],
+            pass_format: PassFormat::Png,
+            denoise: false,
+            denoise_strength: None,
+            denoise_compare: false,
+            samples: None,
+            time_limit: None,
+            noise_threshold: None,
+            flush_interval: None,
+            seed: None,
+            checkpoint: None,
+            resume: false,
+            stats_json: None,
+            quiet: false,
+            help: false,
+        }
+    }
+}
+
+impl Args {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut positional = 0;
+        let mut scene_given = false;
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", name))
+            };
+            match arg.as_str() {
+                "-h" | "--help" => parsed.help = true,
+                "-o" | "--output" => parsed.output = PathBuf::from(value(&arg)?),
+                "--set" => {
+                    let (name, value) = parse_variable(&value(&arg)?)?;
+                    parsed.variables.insert(name, value);
+                }
+                "--frames" => parsed.frames = Some(parse_frames(&value(&arg)?)?),
+                "--fps" => parsed.fps = Some(parse_fps(&value(&arg)?)?),
+                "--passes" => parsed.passes = parse_passes(&value(&arg)?)?,
+                "--pass-format" => {
+                    parsed.pass_format = match value(&arg)?.as_str() {
+                        "png" => PassFormat::Png,
+                        "exr" => PassFormat::Exr,
+                        other => return Err(format!("unknown pass format '{}'", other)),
+                    }
+                }
+                "--denoise" => parsed.denoise = true,
+                "--denoise-strength" => {
+                    parsed.denoise_strength = Some(parse_number(&arg, &value(&arg)?)?)
+                }
+                "--denoise-compare" => parsed.denoise_compare = true,
+                "--samples" => parsed.samples = Some(parse_number(&arg, &value(&arg)?)?),
+                "--time-limit" => parsed.time_limit = Some(parse_number(&arg, &value(&arg)?)?),
+                "--noise-threshold" => {
+                    parsed.noise_threshold = Some(parse_number(&arg, &value(&arg)?)?)
+                }
+                "--flush-interval" => {
+                    parsed.flush_interval = Some(parse_number(&arg, &value(&arg)?)?)
+                }
+                "--seed" => parsed.seed = Some(parse_number(&arg, &value(&arg)?)?),
+                "--checkpoint" => parsed.checkpoint = Some(PathBuf::from(value(&arg)?)),
+                "--resume" => parsed.resume = true,
+                "--stats-json" => parsed.stats_json = Some(PathBuf::from(value(&arg)?)),
+                "-q" | "--quiet" => parsed.quiet = true,
+                flag if flag.starts_with('-') => return Err(format!("unknown option '{}'", flag)),
+                name if positional == 0 && Command::from_name(name).is_some() => {
+                    parsed.command = Command::from_name(name).unwrap();
+                    positional += 1;
+                }
+                path if scene_given && parsed.command == Command::Convert => {
+                    parsed.output = PathBuf::from(path)
+                }
+                path => {
+                    parsed.scene = PathBuf::from(path);
+                    scene_given = true;
+                    positional += 1;
+                }
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Denoiser from the scene with command line overrides
+    pub fn denoiser(&self, scene: Option<Denoiser>) -> Option<Denoiser> {
+        let denoiser = match (scene, self.denoise) {
+            (Some(d), _) => d,
+            (None, true) => Denoiser::default(),
+            (None, false) => return None,
+        };
+        Some(Denoiser {
+            strength: self.denoise_strength.unwrap_or(denoiser.strength),
+            ..denoiser
+        })
+    }
+
+    /// Progressive settings from the scene with command line overrides.
+    /// Any of the budget flags or `--resume` switches progressive rendering on.
+    pub fn progressive(&self, scene: Option<Progressive>) -> Option<Progressive> {
+        let requested = self.samples.is_some()
+            || self.time_limit.is_some()
+            || self.noise_threshold.is_some()
+            || self.resume;
+        let settings = match scene {
+            Some(p) => p,
+            None if requested => Progressive::default(),
+            None => return None,
+        };
+        Some(Progressive {
+            samples: self.samples.or(settings.samples),
+            time_limit: self.time_limit.or(settings.time_limit),
+            noise_threshold: self.noise_threshold.or(settings.noise_threshold),
+            flush_interval: self.flush_interval.unwrap_or(settings.flush_interval),
+            seed: self.seed.unwrap_or(settings.seed),
+            ..settings
+        })
+    }
+}
+
+/// `floor_y=-2` or `color={"red": 1, "green": 0, "blue": 0}`, anything that isn't json is a string
+fn parse_variable(assignment: &str) -> Result<(String, Value), String> {
+    let (name, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got '{}'", assignment))?;
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+    Ok((name.trim().to_string(), value))
+}
+
+/// `10..20`, or a single frame `12`
+fn parse_frames(range: &str) -> Result<(u32, u32), String> {
+    let (start, end) = range.split_once("..").unwrap_or((range, range));
+    let start = parse_number("--frames", start)?;
+    let end = parse_number("--frames", end)?;
+    if start > end {
+        return Err(format!("--frames {} ends before it starts", range));
+    }
+    Ok((start, end))
+}
+
+/// Frame times are `frame / fps`, so anything but a positive rate breaks them
+fn parse_fps(value: &str) -> Result<f64, String> {
+    let fps: f64 = parse_number("--fps", value)?;
+    if !fps.is_finite() || fps <= 0.0 {
+        return Err(format!("--fps has to be positive, got {}", value));
+    }
+    Ok(fps)
+}
+
+fn parse_passes(list: &str) -> Result<Vec<Pass>, String> {
+    if list == "all" {
+        return Ok(ALL_PASSES.to_vec());
+    }
+    list.split(',')
+        .map(|name| Pass::from_name(name.trim()).ok_or_else(|| format!("unknown pass '{}'", name)))
+        .collect()
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn fps_has_to_be_positive() {
+        assert_eq!(parse(&["animate", "--fps", "24"]).unwrap().fps, Some(24.0));
+        for fps in &["0", "-1", "NaN", "inf"] {
+            assert!(parse(&["animate", "--fps", fps]).is_err(), "--fps {}", fps);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Metals. A conductor reflects all of the light it doesn't absorb, and how much that is
+//! depends on the angle and the channel: the complex index of refraction `eta + i k` of the
+//! metal gives its color straight on and its whiter, brighter edges at grazing angles.
+use crate::{entities::Color, vector::Vector3};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum Conductor {
+    Gold,
+    Copper,
+    Silver,
+    Aluminum,
+    /// Any other metal, indices at roughly 650, 550 and 450 nm for red, green and blue
+    Custom {
+        eta: Color,
+        k: Color,
+    },
+}
+
+impl Conductor {
+    /// Complex index of refraction per channel
+    pub fn ior(&self) -> (Color, Color) {
+        match *self {
+            Conductor::Gold => (
+                Color::new(0.143, 0.374, 1.442),
+                Color::new(3.983, 2.385, 1.603),
+            ),
+            Conductor::Copper => (
+                Color::new(0.200, 0.924, 1.102),
+                Color::new(3.912, 2.452, 2.142),
+            ),
+            Conductor::Silver => (
+                Color::new(0.155, 0.117, 0.138),
+                Color::new(4.828, 3.122, 2.147),
+            ),
+            Conductor::Aluminum => (
+                Color::new(1.657, 0.880, 0.521),
+                Color::new(9.224, 6.270, 4.837),
+            ),
+            Conductor::Custom { eta, k } => (eta, k),
+        }
+    }
+
+    /// Share of the light reflected per channel, coming in along `incident`
+    pub fn fresnel(&self, incident: Vector3, normal: Vector3) -> Color {
+        let cos_i = incident.dot(&normal).abs().min(1.0);
+        let (eta, k) = self.ior();
+        Color::new(
+            fresnel_conductor(cos_i, eta.red as f64, k.red as f64),
+            fresnel_conductor(cos_i, eta.green as f64, k.green as f64),
+            fresnel_conductor(cos_i, eta.blue as f64, k.blue as f64),
+        )
+    }
+
+    /// The metal's color looking straight at it
+    pub fn color(&self) -> Color {
+        self.fresnel(Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, 1.0))
+    }
+}
+
+/// Unpolarized Fresnel reflectance of a conductor with index `eta + i k`,
+/// lit from outside at angle `acos(cos_i)`
+fn fresnel_conductor(cos_i: f64, eta: f64, k: f64) -> f32 {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos_i * a;
+    let r_s = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+    ((r_s + r_p) / 2.0) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NORMAL: Vector3 = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+    };
+
+    fn custom(eta: f32, k: f32) -> Conductor {
+        Conductor::Custom {
+            eta: Color::new(eta, eta, eta),
+            k: Color::new(k, k, k),
+        }
+    }
+
+    #[test]
+    fn normal_incidence() {
+        for &(eta, k) in &[(0.143, 3.983), (1.657, 9.224), (1.5, 0.0), (0.2, 0.5)] {
+            let (e, k2) = (eta as f64, (k as f64).powi(2));
+            let expected = ((e - 1.0).powi(2) + k2) / ((e + 1.0).powi(2) + k2);
+            let reflected = custom(eta, k).fresnel(Vector3::new(0.0, 0.0, -1.0), NORMAL);
+            assert!(
+                (reflected.red as f64 - expected).abs() < 1e-5,
+                "eta {} k {}: {} instead of {}",
+                eta,
+                k,
+                reflected.red,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn grazing_incidence_reflects_everything() {
+        let grazing = Vector3::new(1.0, 0.0, 0.0);
+        for metal in &[Conductor::Gold, Conductor::Aluminum, custom(1.5, 0.0)] {
+            let reflected = metal.fresnel(grazing, NORMAL);
+            for channel in &[reflected.red, reflected.green, reflected.blue] {
+                assert!((channel - 1.0).abs() < 1e-5, "{:?}: {:?}", metal, reflected);
+            }
+        }
+    }
+
+    #[test]
+    fn reflectance_grows_towards_grazing() {
+        let metal = Conductor::Copper;
+        let straight = metal.fresnel(Vector3::new(0.0, 0.0, -1.0), NORMAL);
+        let slanted = metal.fresnel(Vector3::new(0.99, 0.0, -0.141).normalize(), NORMAL);
+        assert!(slanted.blue > straight.blue);
+    }
+
+    #[test]
+    fn presets() {
+        let gold = Conductor::Gold.color();
+        assert!(gold.red > 0.9, "{:?}", gold);
+        assert!(
+            gold.red > gold.green && gold.green > gold.blue,
+            "{:?}",
+            gold
+        );
+        let silver = Conductor::Silver.color();
+        assert!(
+            silver.red > 0.9 && silver.green > 0.9 && silver.blue > 0.9,
+            "{:?}",
+            silver
+        );
+    }
+}
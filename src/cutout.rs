@@ -0,0 +1,162 @@
+//! Alpha cutouts for leaves, fences and decals. Where a material's alpha falls below its
+//! threshold the element isn't there at all: camera, reflection and shadow rays all go on
+//! to whatever is behind.
+use crate::{
+    entities::{Element, Texture},
+    rendering::{Intersectable, Ray},
+};
+use serde_derive::{Deserialize, Serialize};
+
+/// How far a ray moves past a cut out hit before looking for the next one
+const CUTOUT_STEP: f64 = 1e-9;
+/// Cut out hits a ray passes on one element at most, a sphere has two
+const MAX_CUTOUT_HITS: u32 = 4;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Cutout {
+    /// Alpha below which the surface is cut away
+    #[serde(default = "Cutout::default_threshold")]
+    pub threshold: f32,
+    /// Takes the alpha from this texture instead of the material's coloration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask: Option<Texture>,
+}
+
+impl Cutout {
+    fn default_threshold() -> f32 {
+        0.5
+    }
+}
+
+impl Element {
+    /// Distance to the first hit on the element that isn't cut out
+    pub fn intersect_opaque(&self, ray: &Ray) -> Option<f64> {
+        let material = self.material();
+        let cutout = match &material.cutout {
+            Some(cutout) => cutout,
+            None => return self.intersect(ray),
+        };
+        let mut skipped = 0.0;
+        let mut ray = *ray;
+        for _ in 0..MAX_CUTOUT_HITS {
+            let distance = self.intersect(&ray)?;
+            let hit = ray.origin + ray.direction * distance - self.offset_at(ray.time);
+            let coords = self.texture_coords(&hit);
+            let alpha = match &cutout.mask {
+                Some(mask) => mask.pixel(&coords).0[3] as f32 / 255.0,
+                None => material.coloration.alpha(&coords),
+            };
+            if alpha >= cutout.threshold {
+                return Some(skipped + distance);
+            }
+            skipped += distance + CUTOUT_STEP;
+            ray.origin = ray.origin + ray.direction * (distance + CUTOUT_STEP);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::lit_sphere,
+        entities::{Material, Sphere},
+        point::Point,
+        rendering::WHITE,
+        vector::Vector3,
+    };
+    use image::{DynamicImage, Rgba, RgbaImage};
+
+    /// A mask one texel high with the given alphas side by side
+    fn mask(alphas: &[u8]) -> Cutout {
+        let mut image = RgbaImage::new(alphas.len() as u32, 1);
+        for (x, alpha) in alphas.iter().enumerate() {
+            image.put_pixel(x as u32, 0, Rgba([255, 255, 255, *alpha]));
+        }
+        Cutout {
+            threshold: Cutout::default_threshold(),
+            mask: Some(Texture {
+                path: "mask.png".into(),
+                image: DynamicImage::ImageRgba8(image),
+            }),
+        }
+    }
+
+    fn cut_out(cutout: Cutout) -> Material {
+        Material {
+            cutout: Some(cutout),
+            ..Material::diffuse(WHITE)
+        }
+    }
+
+    fn forward() -> Ray {
+        Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            time: 0.0,
+            wavelength: None,
+        }
+    }
+
+    #[test]
+    fn rays_go_through_holes_to_what_is_behind() {
+        let scene = lit_sphere(Material::diffuse(WHITE))
+            .plane(
+                Point::new(0.0, 0.0, -3.0),
+                Vector3::new(0.0, 0.0, -1.0),
+                cut_out(mask(&[0])),
+            )
+            .build();
+        let hit = scene.trace(&forward()).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-6);
+        assert!(matches!(hit.element, Element::Sphere(_)));
+    }
+
+    #[test]
+    fn the_far_side_shows_through_a_cut_out_near_side() {
+        // texture x is 0.75 on the side facing the camera and 0.25 on the far side
+        let sphere = Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: cut_out(mask(&[255, 0])),
+            motion: None,
+        });
+        let distance = sphere.intersect_opaque(&forward()).unwrap();
+        assert!((distance - 6.0).abs() < 1e-6, "{}", distance);
+        let solid = Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: cut_out(mask(&[255, 255])),
+            motion: None,
+        });
+        assert!((solid.intersect_opaque(&forward()).unwrap() - 4.0).abs() < 1e-6);
+        let gone = Element::Sphere(Sphere {
+            center: Point::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material: cut_out(mask(&[0, 0])),
+            motion: None,
+        });
+        assert!(gone.intersect_opaque(&forward()).is_none());
+    }
+
+    #[test]
+    fn shadows_go_through_holes() {
+        // the unit sphere's top lies under a roof, lit from straight above
+        let under = |roof: Material| {
+            lit_sphere(Material::diffuse(WHITE))
+                .plane(
+                    Point::new(0.0, 3.0, -5.0),
+                    Vector3::new(0.0, 1.0, 0.0),
+                    roof,
+                )
+                .build()
+        };
+        let top = Point::new(0.0, 1.0, -5.0);
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        let solid = under(Material::diffuse(WHITE));
+        assert_eq!(solid.shadow_fraction(top, up, 0.0), 1.0);
+        let holey = under(cut_out(mask(&[0])));
+        assert_eq!(holey.shadow_fraction(top, up, 0.0), 0.0);
+    }
+}
@@ -0,0 +1,291 @@
+//! Edge-aware à-trous wavelet denoiser.
+//!
+//! Each iteration is a 5x5 B-spline blur with holes between the taps doubling every time,
+//! so a few iterations cover a wide footprint cheaply. Every tap is weighted by how similar
+//! its color, normal, depth and albedo are to the center pixel, which keeps edges and texture
+//! detail intact (Dammertz et al. "Edge-Avoiding À-Trous Wavelet Transform").
+use crate::{
+    entities::Color,
+    passes::{PassBuffers, PassSample},
+    rendering::BLACK,
+};
+use image::{DynamicImage, GenericImage};
+use serde_derive::{Deserialize, Serialize};
+
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct Denoiser {
+    /// Blend between the noisy (0.0) and the filtered (1.0) image
+    pub strength: f32,
+    /// Every iteration doubles the filter footprint
+    pub iterations: u32,
+    pub sigma_color: f32,
+    pub sigma_normal: f32,
+    pub sigma_depth: f32,
+    pub sigma_albedo: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser {
+            strength: 1.0,
+            iterations: 4,
+            sigma_color: 0.5,
+            sigma_normal: 0.1,
+            sigma_depth: 0.5,
+            sigma_albedo: 0.1,
+        }
+    }
+}
+
+fn distance2(a: Color, b: Color) -> f32 {
+    let d = a - b;
+    d.red * d.red + d.green * d.green + d.blue * d.blue
+}
+
+fn divide(color: Color, albedo: Color) -> Color {
+    let safe = |c: f32, a: f32| if a > 1e-3 { c / a } else { c };
+    Color::new(
+        safe(color.red, albedo.red),
+        safe(color.green, albedo.green),
+        safe(color.blue, albedo.blue),
+    )
+}
+
+fn multiply(color: Color, albedo: Color) -> Color {
+    let safe = |c: f32, a: f32| if a > 1e-3 { c * a } else { c };
+    Color::new(
+        safe(color.red, albedo.red),
+        safe(color.green, albedo.green),
+        safe(color.blue, albedo.blue),
+    )
+}
+
+impl Denoiser {
+    /// Filters the beauty image stored in `buffers`, guided by its normal, depth and albedo passes
+    pub fn denoise(&self, buffers: &PassBuffers) -> Vec<Color> {
+        let (width, height) = (buffers.width as i64, buffers.height as i64);
+        let guides = buffers.samples();
+        let noisy: Vec<Color> = guides.iter().map(|s| s.beauty()).collect();
+
+        // filter the lighting only, textures are put back afterwards
+        let mut current: Vec<Color> = guides
+            .iter()
+            .map(|s| divide(s.beauty(), s.albedo))
+            .collect();
+
+        for iteration in 0..self.iterations {
+            let step = 1i64 << iteration;
+            // finer levels have already removed most noise, be stricter with colors
+            let sigma_color = self.sigma_color / (1u32 << iteration) as f32;
+            let mut next = vec![BLACK; current.len()];
+
+            for y in 0..height {
+                for x in 0..width {
+                    let p = (y * width + x) as usize;
+                    let mut sum = BLACK;
+                    let mut weights = 0.0;
+                    for (j, ky) in KERNEL.iter().enumerate() {
+                        for (i, kx) in KERNEL.iter().enumerate() {
+                            let qx = x + (i as i64 - 2) * step;
+                            let qy = y + (j as i64 - 2) * step;
+                            if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                                continue;
+                            }
+                            let q = (qy * width + qx) as usize;
+                            let w = kx
+                                * ky
+                                * self.edge_weight(
+                                    &guides[p],
+                                    &guides[q],
+                                    current[p],
+                                    current[q],
+                                    sigma_color,
+                                );
+                            sum = sum + current[q] * w;
+                            weights += w;
+                        }
+                    }
+                    next[p] = if weights > 0.0 {
+                        sum * (1.0 / weights)
+                    } else {
+                        current[p]
+                    };
+                }
+            }
+            current = next;
+        }
+
+        let strength = self.strength.clamp(0.0, 1.0);
+        current
+            .iter()
+            .zip(guides)
+            .zip(&noisy)
+            .map(|((c, s), n)| multiply(*c, s.albedo) * strength + *n * (1.0 - strength))
+            .collect()
+    }
+
+    fn edge_weight(
+        &self,
+        p: &PassSample,
+        q: &PassSample,
+        color_p: Color,
+        color_q: Color,
+        sigma_color: f32,
+    ) -> f32 {
+        let color = (-distance2(color_p, color_q) / (sigma_color * sigma_color).max(1e-6)).exp();
+        let normal = {
+            let d = (1.0 - p.normal.dot(&q.normal) as f32).max(0.0);
+            (-d / self.sigma_normal.max(1e-6)).exp()
+        };
+        let depth = if p.depth.is_finite() && q.depth.is_finite() {
+            (-(p.depth - q.depth).abs() / self.sigma_depth.max(1e-6)).exp()
+        } else if p.depth.is_finite() != q.depth.is_finite() {
+            // never blend the background into geometry
+            0.0
+        } else {
+            1.0
+        };
+        let albedo = (-distance2(p.albedo, q.albedo)
+            / (self.sigma_albedo * self.sigma_albedo).max(1e-6))
+        .exp();
+        color * normal * depth * albedo
+    }
+}
+
+pub fn colors_to_image(width: u32, height: u32, colors: &[Color]) -> DynamicImage {
+    let mut image = DynamicImage::new_rgb8(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            image.put_pixel(x, y, colors[(y * width + x) as usize].to_rgba());
+        }
+    }
+    image
+}
+
+/// Noisy image on the left, denoised on the right
+pub fn side_by_side(noisy: &DynamicImage, denoised: &DynamicImage) -> DynamicImage {
+    let mut image = DynamicImage::new_rgb8(noisy.width() * 2, noisy.height());
+    image
+        .copy_from(noisy, 0, 0)
+        .and_then(|_| image.copy_from(denoised, noisy.width(), 0))
+        .expect("Both halves have the same size");
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sampling::Rng, vector::Vector3};
+
+    const SIZE: u32 = 16;
+
+    /// A wall facing the camera, lit by `light(x, y)` and colored `albedo(x, y)`
+    fn wall(light: impl Fn(u32, u32) -> f32, albedo: impl Fn(u32, u32) -> f32) -> PassBuffers {
+        let mut buffers = PassBuffers::new(SIZE, SIZE);
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let a = albedo(x, y);
+                let l = light(x, y);
+                buffers.set(
+                    x,
+                    y,
+                    PassSample {
+                        depth: 1.0,
+                        normal: Vector3::new(0.0, 0.0, 1.0),
+                        albedo: Color::new(a, a, a),
+                        direct: Color::new(a * l, a * l, a * l),
+                        ..PassSample::default()
+                    },
+                );
+            }
+        }
+        buffers
+    }
+
+    fn assert_close(colors: &[Color], expected: impl Fn(u32, u32) -> f32) {
+        for (i, c) in colors.iter().enumerate() {
+            let (x, y) = (i as u32 % SIZE, i as u32 / SIZE);
+            let e = expected(x, y);
+            assert!(
+                (c.red - e).abs() < 1e-3,
+                "({}, {}): {} instead of {}",
+                x,
+                y,
+                c.red,
+                e
+            );
+        }
+    }
+
+    /// Colors alone don't stop the blur, only the guides do
+    fn blind() -> Denoiser {
+        Denoiser {
+            sigma_color: 1e6,
+            ..Denoiser::default()
+        }
+    }
+
+    #[test]
+    fn constant_stays_constant() {
+        let denoised = Denoiser::default().denoise(&wall(|_, _| 0.6, |_, _| 0.5));
+        assert_close(&denoised, |_, _| 0.3);
+    }
+
+    #[test]
+    fn textures_survive() {
+        let checker = |x: u32, y: u32| if (x + y).is_multiple_of(2) { 0.2 } else { 0.8 };
+        let denoised = blind().denoise(&wall(|_, _| 0.5, checker));
+        assert_close(&denoised, |x, y| checker(x, y) * 0.5);
+    }
+
+    #[test]
+    fn noise_goes_down() {
+        let mut rng = Rng::new(7);
+        let noise: Vec<f32> = (0..SIZE * SIZE)
+            .map(|_| 0.5 + 0.2 * (rng.next_f32() - 0.5))
+            .collect();
+        let buffers = wall(|x, y| noise[(y * SIZE + x) as usize], |_, _| 1.0);
+        let variance = |colors: &[Color]| {
+            let mean = colors.iter().map(|c| c.red).sum::<f32>() / colors.len() as f32;
+            colors.iter().map(|c| (c.red - mean).powi(2)).sum::<f32>() / colors.len() as f32
+        };
+        let noisy: Vec<Color> = buffers.samples().iter().map(|s| s.beauty()).collect();
+        let denoised = Denoiser::default().denoise(&buffers);
+        assert!(variance(&denoised) < variance(&noisy) / 4.0);
+    }
+
+    #[test]
+    fn normal_edges_stay_sharp() {
+        let mut buffers = wall(|x, _| if x < SIZE / 2 { 1.0 } else { 0.0 }, |_, _| 1.0);
+        for y in 0..SIZE {
+            for x in SIZE / 2..SIZE {
+                let sample = PassSample {
+                    normal: Vector3::new(1.0, 0.0, 0.0),
+                    ..*buffers.get(x, y)
+                };
+                buffers.set(x, y, sample);
+            }
+        }
+        let denoised = blind().denoise(&buffers);
+        assert_close(&denoised, |x, _| if x < SIZE / 2 { 1.0 } else { 0.0 });
+    }
+
+    #[test]
+    fn depth_edges_stay_sharp() {
+        let mut buffers = wall(|x, _| if x < SIZE / 2 { 1.0 } else { 0.0 }, |_, _| 1.0);
+        for y in 0..SIZE {
+            for x in SIZE / 2..SIZE {
+                let sample = PassSample {
+                    depth: 10.0,
+                    ..*buffers.get(x, y)
+                };
+                buffers.set(x, y, sample);
+            }
+        }
+        let denoised = blind().denoise(&buffers);
+        assert_close(&denoised, |x, _| if x < SIZE / 2 { 1.0 } else { 0.0 });
+    }
+}
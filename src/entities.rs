@@ -1,15 +1,26 @@
-use crate::{point::Point, rendering::TextureCoords, vector::Vector3};
+use crate::{
+    animation::{segment, Interpolation},
+    conductor::Conductor,
+    cutout::Cutout,
+    point::Point,
+    relief::Relief,
+    rendering::{TextureCoords, BLACK},
+    spectral::RefractiveIndex,
+    vector::Vector3,
+    volume::Medium,
+};
 use image::{DynamicImage, GenericImageView, Pixel, Rgba};
-use serde::{Deserialize, Deserializer};
-use serde_derive::Deserialize;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_derive::{Deserialize, Serialize};
 
 use std::{
     fmt,
-    ops::{Add, Mul},
+    ops::{Add, Mul, Sub},
     path::PathBuf,
 };
 
 pub const DEPTH: u32 = 0;
+pub const TILE_SIZE: u32 = 32;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -20,7 +31,31 @@ pub struct ViewBlock {
     pub height: u32,
 }
 
-#[derive(Deserialize, Clone, Copy, Debug)]
+impl ViewBlock {
+    /// Splits the image into `TILE_SIZE` squares row by row,
+    /// the ones on the right and bottom edges may be smaller.
+    pub fn tiles(width: u32, height: u32) -> Vec<ViewBlock> {
+        let mut tiles = vec![];
+        for y in (0..height).step_by(TILE_SIZE as usize) {
+            for x in (0..width).step_by(TILE_SIZE as usize) {
+                tiles.push(ViewBlock {
+                    x,
+                    y,
+                    width: TILE_SIZE.min(width - x),
+                    height: TILE_SIZE.min(height - y),
+                });
+            }
+        }
+        tiles
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (self.y..self.y + self.height)
+            .flat_map(move |y| (self.x..self.x + self.width).map(move |x| (x, y)))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 pub struct Color {
     pub red: f32,
     pub green: f32,
@@ -38,6 +73,17 @@ impl Add for Color {
         }
     }
 }
+impl Sub for Color {
+    type Output = Color;
+
+    fn sub(self, other: Color) -> Color {
+        Color {
+            red: self.red - other.red,
+            blue: self.blue - other.blue,
+            green: self.green - other.green,
+        }
+    }
+}
 impl Mul for Color {
     type Output = Color;
 
@@ -79,11 +125,7 @@ fn gamma_decode(encoded: f32) -> f32 {
 
 impl Color {
     pub fn new(red: f32, green: f32, blue: f32) -> Color {
-        Color {
-            red: red,
-            green: green,
-            blue: blue,
-        }
+        Color { red, green, blue }
     }
     pub fn to_rgba(self) -> Rgba<u8> {
         Rgba::from_slice(&[
@@ -103,35 +145,61 @@ impl Color {
     }
     pub fn clamp(&self) -> Color {
         Color {
-            red: self.red.min(1.0).max(0.0),
-            blue: self.blue.min(1.0).max(0.0),
-            green: self.green.min(1.0).max(0.0),
+            red: self.red.clamp(0.0, 1.0),
+            blue: self.blue.clamp(0.0, 1.0),
+            green: self.green.clamp(0.0, 1.0),
         }
     }
 }
 
-pub fn load_texture<'a, D>(deserializer: D) -> Result<DynamicImage, D::Error>
-where
-    D: Deserializer<'a>,
-{
-    let path = PathBuf::deserialize(deserializer)?;
-    Ok(image::open(path).expect("Unable to open texture file"))
+/// Image texture. It is written to scene files as the path it was loaded from.
+pub struct Texture {
+    pub path: PathBuf,
+    pub image: DynamicImage,
 }
 
-#[derive(Deserialize)]
-pub enum Coloration {
-    Color(Color),
-    Texture(#[serde(deserialize_with = "load_texture")] DynamicImage),
+impl Texture {
+    pub fn open<P: Into<PathBuf>>(path: P) -> image::ImageResult<Texture> {
+        let path = path.into();
+        let image = image::open(&path)?;
+        Ok(Texture { path, image })
+    }
+
+    /// The texel under `coords`, the texture repeats in both directions
+    pub fn pixel(&self, coords: &TextureCoords) -> Rgba<u8> {
+        let tex_x = wrap(coords.x, self.image.width());
+        let tex_y = wrap(coords.y, self.image.height());
+        self.image.get_pixel(tex_x, tex_y)
+    }
+}
+
+impl Serialize for Texture {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.path.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Texture {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Texture, D::Error> {
+        let path = PathBuf::deserialize(deserializer)?;
+        Texture::open(&path).map_err(|err| {
+            de::Error::custom(format!("can't open texture {}: {}", path.display(), err))
+        })
+    }
 }
-impl fmt::Debug for Coloration {
+
+impl fmt::Debug for Texture {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Coloration::Color(c) => write!(f, "Color({:?})", c),
-            Coloration::Texture(_) => write!(f, "Texture"),
-        }
+        write!(f, "Texture({:?})", self.path)
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Coloration {
+    Color(Color),
+    Texture(Texture),
+}
+
 fn wrap(val: f32, bound: u32) -> u32 {
     let signed_bound = bound as i32;
     let float_coord = val * bound as f32;
@@ -146,39 +214,120 @@ fn wrap(val: f32, bound: u32) -> u32 {
 impl Coloration {
     pub fn color(&self, coords: &TextureCoords) -> Color {
         match self {
-            Coloration::Color(c) => c.clone(),
-            Coloration::Texture(texture) => {
-                let tex_x = wrap(coords.x, texture.width());
-                let tex_y = wrap(coords.y, texture.height());
+            Coloration::Color(c) => *c,
+            Coloration::Texture(texture) => Color::from_rgba(texture.pixel(coords)),
+        }
+    }
 
-                Color::from_rgba(texture.get_pixel(tex_x, tex_y))
-            }
+    /// Opacity from 0 to 1, plain colors and textures without an alpha channel are opaque
+    pub fn alpha(&self, coords: &TextureCoords) -> f32 {
+        match self {
+            Coloration::Color(_) => 1.0,
+            Coloration::Texture(texture) => texture.pixel(coords).0[3] as f32 / 255.0,
         }
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub enum SurfaceType {
     Diffuse,
-    Reflective { reflectivity: f32 },
-    Refractive { index: f32, transparency: f32 },
+    Reflective {
+        reflectivity: f32,
+    },
+    Refractive {
+        index: RefractiveIndex,
+        /// Share of the light getting through, shadow rays are dimmed by it once per element
+        transparency: f32,
+    },
+    /// Metal, reflecting more the flatter the angle, `{"Conductor": "Gold"}`
+    Conductor(Conductor),
 }
 
-#[derive(Deserialize, Debug)]
+/// Light given off by a surface itself, `coloration` times `strength`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Emission {
+    pub coloration: Coloration,
+    pub strength: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Material {
     pub coloration: Coloration,
     pub albedo: f32,
     pub surface: SurfaceType,
+    /// Makes the element glow. Path tracing also lights other elements with it, but only
+    /// spheres are aimed at as light sources, a glowing plane is only found by rays that
+    /// happen to bounce into it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emission: Option<Emission>,
+    /// Fills the inside of the element, the surface becomes its boundary. Shadow rays
+    /// pass through it dimmed, a refractive surface with index 1 doesn't show at all.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub medium: Option<Medium>,
+    /// Bends the normals used for shading, so the surface looks rough or carved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relief: Option<Relief>,
+    /// Cuts holes where the coloration's alpha is low, rays pass through them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cutout: Option<Cutout>,
+}
+
+impl Material {
+    pub fn emitted(&self, coords: &TextureCoords) -> Color {
+        match &self.emission {
+            Some(emission) => emission.coloration.color(coords) * emission.strength,
+            None => BLACK,
+        }
+    }
+}
+
+/// Offset of a moving element from where the scene puts it, at some time
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MotionKey {
+    pub time: f64,
+    pub offset: Vector3,
+    /// How to get from this key to the next one
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+/// How an element moves. It is evaluated at the time of every ray, so with the camera
+/// shutter open for a while fast elements come out blurred.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Motion {
+    /// Units per second, the element is where the scene puts it at time 0
+    Velocity(Vector3),
+    /// Offsets at given times in seconds, in between they are interpolated
+    Keys(Vec<MotionKey>),
+}
+
+impl Motion {
+    pub fn offset_at(&self, time: f64) -> Vector3 {
+        match self {
+            Motion::Velocity(velocity) => *velocity * time,
+            Motion::Keys(keys) if keys.is_empty() => Vector3::zero(),
+            Motion::Keys(keys) => {
+                let times: Vec<f64> = keys.iter().map(|k| k.time).collect();
+                let interpolations: Vec<Interpolation> =
+                    keys.iter().map(|k| k.interpolation).collect();
+                let s = segment(&times, &interpolations, time);
+                let (from, to) = (keys[s.from].offset, keys[s.to].offset);
+                from + (to - from) * s.u
+            }
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Sphere {
     pub center: Point,
     pub radius: f64,
     pub material: Material,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion: Option<Motion>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct DirectionalLight {
     #[serde(deserialize_with = "Vector3::deserialize_normalized")]
     pub direction: Vector3,
@@ -186,17 +335,67 @@ pub struct DirectionalLight {
     pub intensity: f32,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct SphericalLight {
     pub position: Point,
     pub color: Color,
     pub intensity: f32,
 }
 
-#[derive(Deserialize, Debug)]
+/// Point light shining into a cone. Full intensity within `inner_angle` of `direction`,
+/// fading out smoothly towards `outer_angle`, both in degrees from the axis.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SpotLight {
+    pub position: Point,
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub direction: Vector3,
+    pub color: Color,
+    pub intensity: f32,
+    pub inner_angle: f64,
+    pub outer_angle: f64,
+    /// Projected through the cone like a slide, the image fills the outer cone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gobo: Option<Coloration>,
+}
+
+impl SpotLight {
+    /// 1.0 inside the inner cone, 0.0 outside the outer one
+    pub fn falloff(&self, hit_point: &Point) -> f32 {
+        let cos = (*hit_point - self.position)
+            .normalize()
+            .dot(&self.direction);
+        let cos_inner = self.inner_angle.to_radians().cos();
+        let cos_outer = self.outer_angle.to_radians().cos();
+        if cos_inner <= cos_outer {
+            // no soft edge to fade over
+            return if cos >= cos_outer { 1.0 } else { 0.0 };
+        }
+        let t = ((cos - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+        (t * t * (3.0 - 2.0 * t)) as f32
+    }
+
+    /// Where the gobo image lands on `hit_point`, the outer cone maps to [0, 1] on both axes
+    fn gobo_coords(&self, hit_point: &Point) -> TextureCoords {
+        let mut right = self.direction.cross(&Vector3::new(0.0, 1.0, 0.0));
+        if right.length() < 1e-9 {
+            right = self.direction.cross(&Vector3::new(0.0, 0.0, 1.0));
+        }
+        let right = right.normalize();
+        let up = right.cross(&self.direction);
+        let to_hit = *hit_point - self.position;
+        let scale = to_hit.dot(&self.direction) * self.outer_angle.to_radians().tan();
+        TextureCoords {
+            x: (0.5 + 0.5 * to_hit.dot(&right) / scale) as f32,
+            y: (0.5 - 0.5 * to_hit.dot(&up) / scale) as f32,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub enum Light {
     Directional(DirectionalLight),
     Spherical(SphericalLight),
+    Spot(SpotLight),
 }
 
 impl Light {
@@ -204,6 +403,18 @@ impl Light {
         match self {
             Light::Directional(d) => d.color,
             Light::Spherical(s) => s.color,
+            Light::Spot(s) => s.color,
+        }
+    }
+
+    /// Color of the light reaching `hit_point`, a spot light's gobo tints it
+    pub fn color_at(&self, hit_point: &Point) -> Color {
+        match self {
+            Light::Spot(s) => match &s.gobo {
+                Some(gobo) => s.color * gobo.color(&s.gobo_coords(hit_point)),
+                None => s.color,
+            },
+            _ => self.color(),
         }
     }
 
@@ -211,6 +422,7 @@ impl Light {
         match self {
             Light::Directional(d) => -d.direction,
             Light::Spherical(s) => (s.position - *hit_point).normalize(),
+            Light::Spot(s) => (s.position - *hit_point).normalize(),
         }
     }
 
@@ -221,13 +433,18 @@ impl Light {
                 let r2 = (s.position - *hit_point).norm() as f32;
                 s.intensity / (4.0 * ::std::f32::consts::PI * r2)
             }
+            Light::Spot(s) => {
+                let r2 = (s.position - *hit_point).norm() as f32;
+                s.intensity * s.falloff(hit_point) / (4.0 * ::std::f32::consts::PI * r2)
+            }
         }
     }
 
     pub fn distance(&self, hit_point: &Point) -> f64 {
         match self {
-            Light::Directional(_) => ::std::f64::INFINITY,
+            Light::Directional(_) => f64::INFINITY,
             Light::Spherical(s) => (s.position - *hit_point).length(),
+            Light::Spot(s) => (s.position - *hit_point).length(),
         }
     }
 }
@@ -235,21 +452,35 @@ impl Light {
 pub struct Intersection<'a> {
     pub distance: f64,
     pub element: &'a Element,
+    /// How far the element had moved at the time of the ray
+    pub offset: Vector3,
 }
 impl<'a> Intersection<'a> {
-    pub fn new<'b>(distance: f64, element: &'b Element) -> Intersection<'b> {
-        Intersection { distance, element }
+    pub fn new<'b>(distance: f64, element: &'b Element, offset: Vector3) -> Intersection<'b> {
+        Intersection {
+            distance,
+            element,
+            offset,
+        }
+    }
+
+    /// Where a world space hit point is on the element as the scene puts it,
+    /// normals and texture coordinates are taken there
+    pub fn local(&self, hit_point: Point) -> Point {
+        hit_point - self.offset
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Plane {
     pub origin: Point,
     pub normal: Vector3,
     pub material: Material,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion: Option<Motion>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub enum Element {
     Sphere(Sphere),
     Plane(Plane),
@@ -263,6 +494,19 @@ impl Element {
         }
     }
 
+    pub fn motion(&self) -> Option<&Motion> {
+        match self {
+            Element::Sphere(s) => s.motion.as_ref(),
+            Element::Plane(p) => p.motion.as_ref(),
+        }
+    }
+
+    /// How far the element has moved at `time`
+    pub fn offset_at(&self, time: f64) -> Vector3 {
+        self.motion()
+            .map_or(Vector3::zero(), |motion| motion.offset_at(time))
+    }
+
     pub fn material_mut(&mut self) -> &mut Material {
         match *self {
             Element::Sphere(ref mut s) => &mut s.material,
@@ -270,3 +514,86 @@ impl Element {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_at() {
+        let velocity = Motion::Velocity(Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(velocity.offset_at(0.0).x, 0.0);
+        assert_eq!(velocity.offset_at(0.5).x, 1.0);
+        let key = |time: f64, x: f64| MotionKey {
+            time,
+            offset: Vector3::new(x, 0.0, 0.0),
+            interpolation: Interpolation::Linear,
+        };
+        let keys = Motion::Keys(vec![key(1.0, 2.0), key(3.0, 6.0)]);
+        assert_eq!(keys.offset_at(0.0).x, 2.0);
+        assert_eq!(keys.offset_at(2.0).x, 4.0);
+        assert_eq!(keys.offset_at(4.0).x, 6.0);
+        assert_eq!(Motion::Keys(vec![]).offset_at(1.0).length(), 0.0);
+    }
+
+    fn spot() -> SpotLight {
+        SpotLight {
+            position: Point::zero(),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            color: Color::new(1.0, 1.0, 1.0),
+            intensity: 1.0,
+            inner_angle: 10.0,
+            outer_angle: 20.0,
+            gobo: None,
+        }
+    }
+
+    /// 5 units from the light, `degrees` off its axis towards +x
+    fn off_axis(degrees: f64) -> Point {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Point::new(5.0 * sin, 0.0, -5.0 * cos)
+    }
+
+    #[test]
+    fn spot_falloff() {
+        let spot = spot();
+        assert_eq!(spot.falloff(&off_axis(0.0)), 1.0);
+        assert_eq!(spot.falloff(&off_axis(9.0)), 1.0);
+        assert_eq!(spot.falloff(&off_axis(21.0)), 0.0);
+        assert_eq!(spot.falloff(&Point::new(0.0, 0.0, 5.0)), 0.0);
+        let mut last = 1.0;
+        for i in 0..=20 {
+            let falloff = spot.falloff(&off_axis(10.0 + i as f64 / 2.0));
+            assert!(falloff <= last, "{} after {} at step {}", falloff, last, i);
+            last = falloff;
+        }
+        // smoothstep is flat at both ends of the edge
+        let near_inner = 1.0 - spot.falloff(&off_axis(10.5));
+        let middle = spot.falloff(&off_axis(14.5)) - spot.falloff(&off_axis(15.5));
+        assert!(near_inner < middle);
+    }
+
+    #[test]
+    fn spot_without_a_soft_edge() {
+        let spot = SpotLight {
+            inner_angle: 30.0,
+            ..spot()
+        };
+        assert_eq!(spot.falloff(&off_axis(19.0)), 1.0);
+        assert_eq!(spot.falloff(&off_axis(21.0)), 0.0);
+    }
+
+    #[test]
+    fn gobo_fills_the_outer_cone() {
+        let spot = spot();
+        let center = spot.gobo_coords(&off_axis(0.0));
+        assert!((center.x - 0.5).abs() < 1e-6 && (center.y - 0.5).abs() < 1e-6);
+        let edge = spot.gobo_coords(&off_axis(20.0));
+        assert!((edge.x - 1.0).abs() < 1e-5 && (edge.y - 0.5).abs() < 1e-6);
+        let light = Light::Spot(SpotLight {
+            gobo: Some(Coloration::Color(Color::new(1.0, 0.0, 0.0))),
+            ..spot
+        });
+        assert_eq!(light.color_at(&off_axis(5.0)), Color::new(1.0, 0.0, 0.0));
+    }
+}
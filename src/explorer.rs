@@ -0,0 +1,217 @@
+//! Interactive scene explorer in the terminal.
+//!
+//! A low resolution render follows the camera around as you fly it, lighting effects and
+//! recursion depth can be changed on the fly and any of the render passes can be inspected.
+//! The current view can be saved at full resolution at any moment.
+use crate::{
+    passes::{pass_path, Pass, ALL_PASSES},
+    point::Point,
+    preview::{draw_image, draw_status, fit_to_terminal},
+    rendering::Ray,
+    save_image,
+    scene::Scene,
+    stats::Progress,
+};
+use image::DynamicImage;
+use termion::{
+    clear, cursor, event::Key, input::TermRead, raw::IntoRawMode, screen::AlternateScreen, style,
+};
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(30);
+const ORBIT_STEP: f64 = 10.0 * std::f64::consts::PI / 180.0;
+const MOVE_STEP: f64 = 0.5;
+/// Orbit pivot distance when nothing is in the middle of the view
+const DEFAULT_FOCUS: f64 = 5.0;
+
+const HELP: &str =
+    "wasd/rf move, arrows orbit, 1/2/3 effects, +/- depth, p pass, enter save, q quit";
+
+struct Explorer {
+    scene: Scene,
+    full_size: (u32, u32),
+    /// `None` shows the beauty image
+    pass: Option<Pass>,
+    output: PathBuf,
+    saved: u32,
+    message: String,
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+impl Explorer {
+    /// Renders the current view at whatever size the scene is set to
+    fn render(&self) -> DynamicImage {
+        match self.pass {
+            None => self.scene.render(&mut Progress::hidden()),
+            Some(pass) => self
+                .scene
+                .render_passes(&[pass], &mut Progress::hidden())
+                .to_image(pass),
+        }
+    }
+
+    fn status(&self) -> String {
+        let effects = self.scene.effects;
+        format!(
+            " shadows {} | reflections {} | refractions {} | depth {} | {} | {} ",
+            on_off(effects.shadows),
+            on_off(effects.reflections),
+            on_off(effects.refractions),
+            self.scene.max_recursion_depth,
+            self.pass.map_or("beauty", |p| p.name()),
+            if self.message.is_empty() {
+                HELP
+            } else {
+                &self.message
+            },
+        )
+    }
+
+    /// Point in the middle of the view the camera orbits around
+    fn pivot(&self) -> Point {
+        let camera = self.scene.camera;
+        let ray = Ray {
+            origin: camera.position,
+            direction: camera.direction,
+            time: self.scene.time,
+            wavelength: None,
+        };
+        let distance = self.scene.trace(&ray).map_or(DEFAULT_FOCUS, |i| i.distance);
+        camera.position + camera.direction * distance
+    }
+
+    fn next_pass(&mut self) {
+        self.pass = match self.pass {
+            None => Some(ALL_PASSES[0]),
+            Some(pass) => {
+                let i = ALL_PASSES.iter().position(|p| *p == pass).unwrap_or(0);
+                ALL_PASSES.get(i + 1).copied()
+            }
+        };
+    }
+
+    fn save_full_resolution(&mut self) {
+        let preview_size = (self.scene.width, self.scene.height);
+        self.scene.width = self.full_size.0;
+        self.scene.height = self.full_size.1;
+        self.saved += 1;
+        let path = pass_path(&self.output, &format!("view{}", self.saved));
+        save_image(self.render(), &path);
+        self.message = format!("saved {}", path.display());
+        self.scene.width = preview_size.0;
+        self.scene.height = preview_size.1;
+    }
+
+    /// Applies a key press, returns whether the view has to be rendered again
+    fn handle(&mut self, key: Key) -> bool {
+        let camera = &mut self.scene.camera;
+        match key {
+            Key::Char('w') => camera.translate(MOVE_STEP, 0.0, 0.0),
+            Key::Char('s') => camera.translate(-MOVE_STEP, 0.0, 0.0),
+            Key::Char('a') => camera.translate(0.0, -MOVE_STEP, 0.0),
+            Key::Char('d') => camera.translate(0.0, MOVE_STEP, 0.0),
+            Key::Char('r') => camera.translate(0.0, 0.0, MOVE_STEP),
+            Key::Char('f') => camera.translate(0.0, 0.0, -MOVE_STEP),
+            Key::Left | Key::Right | Key::Up | Key::Down => {
+                let pivot = self.pivot();
+                let (yaw, pitch) = match key {
+                    Key::Left => (-ORBIT_STEP, 0.0),
+                    Key::Right => (ORBIT_STEP, 0.0),
+                    Key::Up => (0.0, ORBIT_STEP),
+                    _ => (0.0, -ORBIT_STEP),
+                };
+                self.scene.camera.orbit(pivot, yaw, pitch);
+            }
+            Key::Char('1') => self.scene.effects.shadows = !self.scene.effects.shadows,
+            Key::Char('2') => self.scene.effects.reflections = !self.scene.effects.reflections,
+            Key::Char('3') => self.scene.effects.refractions = !self.scene.effects.refractions,
+            Key::Char('+') | Key::Char('=') => self.scene.max_recursion_depth += 1,
+            Key::Char('-') => {
+                self.scene.max_recursion_depth = self.scene.max_recursion_depth.saturating_sub(1)
+            }
+            Key::Char('p') | Key::Char('\t') => self.next_pass(),
+            Key::Char('\n') => {
+                self.save_full_resolution();
+                return false;
+            }
+            _ => return false,
+        }
+        self.message.clear();
+        true
+    }
+}
+
+/// Flies around the scene in the terminal until `q`, `Esc` or `Ctrl-C`.
+/// Full resolution snapshots are saved next to `output`.
+pub fn explore(scene: Scene, output: &Path) -> io::Result<()> {
+    if !termion::is_tty(&io::stdout()) {
+        return Err(io::Error::other("the explorer needs a terminal"));
+    }
+    let mut keys = termion::async_stdin().keys();
+    let mut screen = AlternateScreen::from(io::stdout().into_raw_mode()?);
+    write!(screen, "{}", cursor::Hide)?;
+
+    let mut explorer = Explorer {
+        full_size: (scene.width, scene.height),
+        scene,
+        pass: None,
+        output: output.to_path_buf(),
+        saved: 0,
+        message: String::new(),
+    };
+    let mut size = (0, 0);
+    let mut dirty = true;
+
+    'explore: loop {
+        let terminal = termion::terminal_size()?;
+        if terminal != size {
+            size = terminal;
+            let (width, height) =
+                fit_to_terminal(explorer.full_size.0, explorer.full_size.1, size.0, size.1);
+            explorer.scene.width = width;
+            explorer.scene.height = height;
+            write!(screen, "{}", clear::All)?;
+            dirty = true;
+        }
+
+        for key in keys.by_ref() {
+            match key? {
+                Key::Char('q') | Key::Esc | Key::Ctrl('c') => break 'explore,
+                Key::Char('\n') => {
+                    draw_status(&mut screen, size, " saving full resolution image...")?;
+                    screen.flush()?;
+                    explorer.handle(Key::Char('\n'));
+                    // saving prints to the terminal
+                    write!(screen, "{}", clear::All)?;
+                    dirty = true;
+                }
+                key => dirty |= explorer.handle(key),
+            }
+        }
+
+        if dirty {
+            let image = explorer.render();
+            draw_image(&mut screen, &image, size.0)?;
+            draw_status(&mut screen, size, &explorer.status())?;
+            screen.flush()?;
+            dirty = false;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    write!(screen, "{}{}", style::Reset, cursor::Show)?;
+    screen.flush()
+}
@@ -0,0 +1,357 @@
+//! Heterogeneous media from voxel grids, for smoke and clouds.
+//!
+//! Grid files are raw little-endian binary: the number of voxels along x, y and z as three
+//! `u32`, then one `f32` density per voxel, x changing fastest and z slowest. The grid
+//! fills a unit cube that `transform` scales, rotates and moves into the scene.
+use crate::{
+    entities::Color,
+    point::Point,
+    rendering::{Ray, WHITE},
+    vector::Vector3,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_derive::{Deserialize, Serialize};
+
+use std::{fmt, fs, io, path::PathBuf};
+
+/// Densities of a voxel grid file. It is written to scene files as the path it was loaded from.
+pub struct DensityGrid {
+    pub path: PathBuf,
+    pub dims: [usize; 3],
+    pub densities: Vec<f32>,
+    /// Highest density in the grid, the majorant for delta tracking
+    pub max: f32,
+}
+
+impl DensityGrid {
+    pub fn open<P: Into<PathBuf>>(path: P) -> io::Result<DensityGrid> {
+        let path = path.into();
+        let bytes = fs::read(&path)?;
+        DensityGrid::parse(path, &bytes)
+    }
+
+    fn parse(path: PathBuf, bytes: &[u8]) -> io::Result<DensityGrid> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let word = |i: usize| -> [u8; 4] {
+            let mut word = [0; 4];
+            word.copy_from_slice(&bytes[i * 4..i * 4 + 4]);
+            word
+        };
+        if bytes.len() < 12 {
+            return Err(invalid("missing the dimensions".to_string()));
+        }
+        let dims = [0, 1, 2].map(|i| u32::from_le_bytes(word(i)) as usize);
+        let count = dims[0]
+            .checked_mul(dims[1])
+            .and_then(|n| n.checked_mul(dims[2]));
+        let size = count.and_then(|n| n.checked_mul(4)?.checked_add(12));
+        let (count, size) = match (count, size) {
+            (Some(0), _) => return Err(invalid(format!("empty grid {:?}", dims))),
+            (Some(count), Some(size)) => (count, size),
+            _ => return Err(invalid(format!("grid {:?} is too big", dims))),
+        };
+        if bytes.len() != size {
+            return Err(invalid(format!(
+                "a {}x{}x{} grid takes {} bytes, the file has {}",
+                dims[0],
+                dims[1],
+                dims[2],
+                size,
+                bytes.len()
+            )));
+        }
+        let densities: Vec<f32> = (3..3 + count)
+            .map(|i| f32::from_le_bytes(word(i)).max(0.0))
+            .collect();
+        let max = densities.iter().copied().fold(0.0, f32::max);
+        Ok(DensityGrid {
+            path,
+            dims,
+            densities,
+            max,
+        })
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.densities[(z * self.dims[1] + y) * self.dims[0] + x]
+    }
+
+    /// Trilinearly interpolated density at a point of the unit cube, 0 outside of it
+    pub fn density_at(&self, p: Vector3) -> f32 {
+        if [p.x, p.y, p.z].iter().any(|c| !(0.0..=1.0).contains(c)) {
+            return 0.0;
+        }
+        // voxel centers sit at (i + 0.5) / n
+        let axis = |c: f64, n: usize| {
+            let f = (c * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            let i = (f as usize).min(n.saturating_sub(2));
+            (i, (i + 1).min(n - 1), (f - i as f64) as f32)
+        };
+        let (x0, x1, fx) = axis(p.x, self.dims[0]);
+        let (y0, y1, fy) = axis(p.y, self.dims[1]);
+        let (z0, z1, fz) = axis(p.z, self.dims[2]);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let plane = |z| {
+            lerp(
+                lerp(self.voxel(x0, y0, z), self.voxel(x1, y0, z), fx),
+                lerp(self.voxel(x0, y1, z), self.voxel(x1, y1, z), fx),
+                fy,
+            )
+        };
+        lerp(plane(z0), plane(z1), fz)
+    }
+}
+
+impl Serialize for DensityGrid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.path.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DensityGrid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<DensityGrid, D::Error> {
+        let path = PathBuf::deserialize(deserializer)?;
+        DensityGrid::open(&path).map_err(|err| {
+            de::Error::custom(format!(
+                "can't read density grid {}: {}",
+                path.display(),
+                err
+            ))
+        })
+    }
+}
+
+impl fmt::Debug for DensityGrid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DensityGrid({:?}, {:?})", self.path, self.dims)
+    }
+}
+
+/// Scales, then rotates, then moves the unit cube
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct Transform {
+    pub translation: Vector3,
+    /// Degrees around x, then y, then z
+    pub rotation: Vector3,
+    pub scale: Vector3,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            translation: Vector3::zero(),
+            rotation: Vector3::zero(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    /// Undoes the rotation of a direction
+    fn unrotate(&self, v: Vector3) -> Vector3 {
+        let [x, y, z] = [self.rotation.x, self.rotation.y, self.rotation.z].map(f64::to_radians);
+        let (sin, cos) = (-z).sin_cos();
+        let v = Vector3::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos, v.z);
+        let (sin, cos) = (-y).sin_cos();
+        let v = Vector3::new(v.x * cos + v.z * sin, v.y, -v.x * sin + v.z * cos);
+        let (sin, cos) = (-x).sin_cos();
+        Vector3::new(v.x, v.y * cos - v.z * sin, v.y * sin + v.z * cos)
+    }
+
+    fn inverse_scale(&self) -> Vector3 {
+        Vector3::new(1.0 / self.scale.x, 1.0 / self.scale.y, 1.0 / self.scale.z)
+    }
+
+    /// Where a scene point is in the unit cube
+    pub fn to_local(&self, p: Point) -> Vector3 {
+        self.unrotate((p - Point::zero()) - self.translation) * self.inverse_scale()
+    }
+
+    /// A direction in unit cube space. It isn't normalized, so distances along a ray stay
+    /// the same in both spaces.
+    pub fn direction_to_local(&self, v: Vector3) -> Vector3 {
+        self.unrotate(v) * self.inverse_scale()
+    }
+}
+
+/// Smoke or cloud with its density read from a voxel grid file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VolumeGrid {
+    pub grid: DensityGrid,
+    #[serde(default)]
+    pub transform: Transform,
+    /// Extinction per unit of distance where the grid's density is 1
+    #[serde(default = "VolumeGrid::default_density")]
+    pub density: f32,
+    /// Share of the extinguished light that is scattered rather than absorbed, per channel
+    #[serde(default = "VolumeGrid::default_albedo")]
+    pub albedo: Color,
+    /// Henyey-Greenstein asymmetry of the scattered light
+    #[serde(default)]
+    pub anisotropy: f64,
+}
+
+impl VolumeGrid {
+    fn default_density() -> f32 {
+        1.0
+    }
+
+    fn default_albedo() -> Color {
+        WHITE
+    }
+
+    /// Highest extinction anywhere in the grid
+    pub fn majorant(&self) -> f64 {
+        (self.grid.max * self.density) as f64
+    }
+
+    /// Extinction at a point of the unit cube
+    pub fn extinction_at(&self, local: Vector3) -> f64 {
+        (self.grid.density_at(local) * self.density) as f64
+    }
+
+    /// The ray in unit cube space and the stretch of it within `[0, distance]` inside the
+    /// cube, if any
+    pub fn clip(&self, ray: &Ray, distance: f64) -> Option<(Vector3, Vector3, f64, f64)> {
+        let origin = self.transform.to_local(ray.origin);
+        let direction = self.transform.direction_to_local(ray.direction);
+        let (mut near, mut far) = (0.0_f64, distance);
+        for (o, d) in [
+            (origin.x, direction.x),
+            (origin.y, direction.y),
+            (origin.z, direction.z),
+        ] {
+            if d.abs() < 1e-12 {
+                if !(0.0..=1.0).contains(&o) {
+                    return None;
+                }
+                continue;
+            }
+            let (t0, t1) = ((0.0 - o) / d, (1.0 - o) / d);
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        if near < far {
+            Some((origin, direction, near, far))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(dims: [u32; 3], densities: &[f32]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = dims.iter().flat_map(|d| d.to_le_bytes()).collect();
+        bytes.extend(densities.iter().flat_map(|d| d.to_le_bytes()));
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> io::Result<DensityGrid> {
+        DensityGrid::parse("test.grid".into(), bytes)
+    }
+
+    fn grid(dims: [u32; 3], densities: &[f32]) -> DensityGrid {
+        parse(&file(dims, densities)).unwrap()
+    }
+
+    #[test]
+    fn broken_files() {
+        for bytes in [
+            vec![1, 0, 0, 0],
+            file([2, 1, 1], &[1.0]),
+            file([1, 1, 1], &[1.0, 2.0]),
+            file([0, 4, 4], &[]),
+            file([u32::MAX, u32::MAX, u32::MAX], &[1.0]),
+        ] {
+            let err = parse(&bytes).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn parses_densities() {
+        let grid = grid([2, 1, 1], &[0.5, -1.0]);
+        assert_eq!(grid.dims, [2, 1, 1]);
+        assert_eq!(grid.densities, vec![0.5, 0.0]);
+        assert_eq!(grid.max, 0.5);
+    }
+
+    #[test]
+    fn voxel_centers_take_their_own_density() {
+        let densities: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let grid = grid([2, 2, 2], &densities);
+        for (i, density) in densities.iter().enumerate() {
+            let [x, y, z] = [i % 2, i / 2 % 2, i / 4].map(|c| (c as f64 + 0.5) / 2.0);
+            let at = grid.density_at(Vector3::new(x, y, z));
+            assert!((at - density).abs() < 1e-6, "{} at voxel {}", at, i);
+        }
+        // halfway between the two x voxels of the first row
+        assert!((grid.density_at(Vector3::new(0.5, 0.25, 0.25)) - 0.5).abs() < 1e-6);
+        assert_eq!(grid.density_at(Vector3::new(1.5, 0.5, 0.5)), 0.0);
+    }
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn to_local() {
+        let transform = Transform {
+            translation: Vector3::new(1.0, 2.0, 3.0),
+            rotation: Vector3::new(0.0, 0.0, 90.0),
+            scale: Vector3::new(2.0, 2.0, 2.0),
+        };
+        assert!(close(
+            transform.to_local(Point::new(1.0, 2.0, 3.0)),
+            Vector3::zero()
+        ));
+        // the cube's x axis is turned onto the scene's y axis
+        assert!(close(
+            transform.to_local(Point::new(1.0, 4.0, 3.0)),
+            Vector3::new(1.0, 0.0, 0.0)
+        ));
+        assert!(close(
+            transform.direction_to_local(Vector3::new(0.0, 2.0, 0.0)),
+            Vector3::new(1.0, 0.0, 0.0)
+        ));
+    }
+
+    fn volume(transform: Transform) -> VolumeGrid {
+        VolumeGrid {
+            grid: grid([1, 1, 1], &[1.0]),
+            transform,
+            density: 1.0,
+            albedo: WHITE,
+            anisotropy: 0.0,
+        }
+    }
+
+    #[test]
+    fn clip() {
+        let volume = volume(Transform {
+            translation: Vector3::new(-1.0, -1.0, -6.0),
+            scale: Vector3::new(2.0, 2.0, 2.0),
+            ..Transform::default()
+        });
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            time: 0.0,
+            wavelength: None,
+        };
+        let (_, _, near, far) = volume.clip(&ray, f64::INFINITY).unwrap();
+        assert!((near - 4.0).abs() < 1e-9 && (far - 6.0).abs() < 1e-9);
+        let (_, _, near, far) = volume.clip(&ray, 5.0).unwrap();
+        assert!((near - 4.0).abs() < 1e-9 && (far - 5.0).abs() < 1e-9);
+        assert!(volume.clip(&ray, 3.0).is_none());
+        let away = Ray {
+            direction: Vector3::new(0.0, 0.0, 1.0),
+            ..ray
+        };
+        assert!(volume.clip(&away, f64::INFINITY).is_none());
+    }
+}
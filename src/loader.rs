@@ -0,0 +1,643 @@
+//! Reading scene files, with errors that can be reported instead of panicking.
+//!
+//! Scenes can be written in JSON, TOML, YAML or RON, picked by the file extension.
+//! Whatever the format, the file is first read into a generic value tree and only then
+//! turned into a `Scene`, so every format describes the scene the exact same way:
+//! enum variants like `Sphere` or `Texture` are single-key maps in all of them.
+//! Includes, variables and named materials are resolved at that stage too.
+use crate::{
+    animation::Animation,
+    scene::Scene,
+    validate::{has_errors, Diagnostic},
+    variables::resolve_variables,
+};
+use serde_json::{Map, Value};
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SceneFormat {
+    Json,
+    Toml,
+    Yaml,
+    Ron,
+}
+
+impl SceneFormat {
+    pub fn from_path(path: &Path) -> Option<SceneFormat> {
+        match path.extension()?.to_str()? {
+            "json" => Some(SceneFormat::Json),
+            "toml" => Some(SceneFormat::Toml),
+            "yaml" | "yml" => Some(SceneFormat::Yaml),
+            "ron" => Some(SceneFormat::Ron),
+            _ => None,
+        }
+    }
+
+    pub fn parse(self, text: &str) -> Result<Value, String> {
+        match self {
+            SceneFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+            SceneFormat::Toml => toml::from_str(text).map_err(|e| e.to_string()),
+            SceneFormat::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+            SceneFormat::Ron => ron::from_str(text).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn write(self, value: &Value) -> Result<String, String> {
+        match self {
+            SceneFormat::Json => serde_json::to_string_pretty(value).map_err(|e| e.to_string()),
+            SceneFormat::Toml => toml::to_string_pretty(value).map_err(|e| e.to_string()),
+            SceneFormat::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+            SceneFormat::Ron => {
+                ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(PathBuf, io::Error),
+    UnknownFormat(PathBuf),
+    Parse(PathBuf, String),
+    /// The scene parsed but failed validation
+    Invalid(PathBuf, Vec<Diagnostic>),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(path, err) => write!(f, "can't read {}: {}", path.display(), err),
+            LoadError::UnknownFormat(path) => write!(
+                f,
+                "{}: unknown scene format, use .json, .toml, .yaml or .ron",
+                path.display()
+            ),
+            LoadError::Parse(path, err) => write!(f, "{}: {}", path.display(), err),
+            LoadError::Invalid(path, diagnostics) => {
+                write!(f, "{} is not a valid scene:", path.display())?;
+                for diagnostic in diagnostics {
+                    write!(f, "\n  {}", diagnostic)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub struct LoadedScene {
+    pub scene: Scene,
+    /// The scene with every reference resolved, checkpoints are tied to it
+    pub bytes: Vec<u8>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Reads a file of any supported format into a value tree, nothing resolved yet
+pub fn read_value(path: &Path) -> Result<Value, LoadError> {
+    let format =
+        SceneFormat::from_path(path).ok_or_else(|| LoadError::UnknownFormat(path.to_path_buf()))?;
+    let text = fs::read_to_string(path).map_err(|err| LoadError::Io(path.to_path_buf(), err))?;
+    format
+        .parse(&text)
+        .map_err(|err| LoadError::Parse(path.to_path_buf(), err))
+}
+
+/// Scene value ready to be turned into a `Scene`
+pub struct SceneSource {
+    pub value: Value,
+    /// The scene file and every file it pulled in
+    pub files: Vec<PathBuf>,
+}
+
+impl SceneSource {
+    /// Files a change of which changes the render
+    pub fn dependencies(&self) -> Vec<PathBuf> {
+        let mut files = self.files.clone();
+        files.extend(texture_paths(&self.value));
+        files
+    }
+}
+
+/// Reads a scene and resolves its includes, variables and named materials.
+/// `variables` override the scene's variables of the same name.
+pub fn read_scene_source(
+    path: &Path,
+    variables: &Map<String, Value>,
+) -> Result<SceneSource, LoadError> {
+    let mut source = read_unresolved_materials(path, variables)?;
+    use_materials(&mut source.value).map_err(|err| LoadError::Parse(path.to_path_buf(), err))?;
+    Ok(source)
+}
+
+/// Everything but the material names resolved, with all known materials in `materials`
+fn read_unresolved_materials(
+    path: &Path,
+    variables: &Map<String, Value>,
+) -> Result<SceneSource, LoadError> {
+    let mut files = vec![];
+    let value = read_with_includes(path, &mut files, &mut vec![])?;
+    let mut source = SceneSource { value, files };
+    let resolve_error = |err| LoadError::Parse(path.to_path_buf(), err);
+    // after gathering, so library materials can use the scene's variables too
+    gather_materials(&mut source).map_err(resolve_error)?;
+    if let Some(scene) = source.value.as_object_mut() {
+        resolve_variables(scene, variables).map_err(resolve_error)?;
+    }
+    Ok(source)
+}
+
+/// Reads a file with everything it includes merged in.
+///
+/// The files listed in `include` come first in order and the including file last, later ones
+/// win: maps are merged key by key, lists like `elements` and `lights` are joined and
+/// anything else is replaced. Relative include paths start in the including file's directory.
+fn read_with_includes(
+    path: &Path,
+    files: &mut Vec<PathBuf>,
+    including: &mut Vec<PathBuf>,
+) -> Result<Value, LoadError> {
+    // `a.json`, `./a.json` and `scenes/../a.json` are all the same file
+    let canonical = fs::canonicalize(path).map_err(|err| LoadError::Io(path.to_path_buf(), err))?;
+    if including.contains(&canonical) {
+        return Err(LoadError::Parse(
+            path.to_path_buf(),
+            "includes itself".to_string(),
+        ));
+    }
+    let mut value = read_value(path)?;
+    files.push(path.to_path_buf());
+    // libraries are only read once everything is merged, when it's no longer known who named them
+    let libraries = value
+        .get_mut("material_libraries")
+        .and_then(Value::as_array_mut);
+    for library in libraries.into_iter().flatten() {
+        if let Value::String(file) = library {
+            *library = Value::String(beside(path, file).to_string_lossy().into_owned());
+        }
+    }
+
+    let includes = match value.as_object_mut().and_then(|map| map.remove("include")) {
+        Some(Value::String(file)) => vec![Value::String(file)],
+        Some(Value::Array(list)) => list,
+        Some(_) => {
+            return Err(LoadError::Parse(
+                path.to_path_buf(),
+                "include: expected a file or a list of files".to_string(),
+            ))
+        }
+        None => return Ok(value),
+    };
+    including.push(canonical);
+    let mut merged = Value::Object(Map::new());
+    for (i, include) in includes.into_iter().enumerate() {
+        let file = include.as_str().map(|f| beside(path, f)).ok_or_else(|| {
+            LoadError::Parse(
+                path.to_path_buf(),
+                format!("include[{}]: expected a file path", i),
+            )
+        })?;
+        merge(&mut merged, read_with_includes(&file, files, including)?);
+    }
+    including.pop();
+    merge(&mut merged, value);
+    Ok(merged)
+}
+
+/// `file` as named in `referring`, relative paths are taken from the directory `referring` is in
+fn beside(referring: &Path, file: &str) -> PathBuf {
+    referring
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(file)
+}
+
+fn merge(into: &mut Value, from: Value) {
+    match (into, from) {
+        (Value::Object(into), Value::Object(from)) => {
+            for (key, value) in from {
+                match into.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        into.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(into), Value::Array(from)) => into.extend(from),
+        (into, from) => *into = from,
+    }
+}
+
+/// A scene with its animation taken out, ready to be turned into any frame
+pub struct AnimatedScene {
+    path: PathBuf,
+    value: Value,
+    pub animation: Option<Animation>,
+}
+
+impl AnimatedScene {
+    pub fn read(path: &Path, variables: &Map<String, Value>) -> Result<AnimatedScene, LoadError> {
+        let mut value = read_unresolved_materials(path, variables)?.value;
+        let parse_error = |err: String| LoadError::Parse(path.to_path_buf(), err);
+        let animation = match value.as_object_mut().and_then(|s| s.remove("animation")) {
+            Some(animation) => {
+                let animation: Animation = serde_json::from_value(animation)
+                    .map_err(|err| parse_error(format!("animation: {}", err)))?;
+                animation.check(&value).map_err(parse_error)?;
+                Some(animation)
+            }
+            None => None,
+        };
+        Ok(AnimatedScene {
+            path: path.to_path_buf(),
+            value,
+            animation,
+        })
+    }
+
+    /// The scene at `time` seconds into the animation, without validating it
+    pub fn at(&self, time: f64) -> Result<LoadedScene, LoadError> {
+        let parse_error = |err: String| LoadError::Parse(self.path.clone(), err);
+        let mut value = self.value.clone();
+        if let Some(animation) = &self.animation {
+            animation.apply(&mut value, time).map_err(parse_error)?;
+            // moving elements follow the frame's time too
+            value["time"] = Value::from(time);
+        }
+        use_materials(&mut value).map_err(parse_error)?;
+        let bytes = serde_json::to_vec(&value).expect("json values always serialize");
+        let scene = serde_json::from_value(value).map_err(|err| parse_error(err.to_string()))?;
+        Ok(LoadedScene {
+            scene,
+            bytes,
+            warnings: vec![],
+        })
+    }
+
+    /// Like `at`, refusing the scene if validation finds errors
+    pub fn frame(&self, time: f64) -> Result<LoadedScene, LoadError> {
+        let mut loaded = self.at(time)?;
+        let diagnostics = loaded.scene.validate();
+        if has_errors(&diagnostics) {
+            return Err(LoadError::Invalid(self.path.clone(), diagnostics));
+        }
+        loaded.warnings = diagnostics;
+        Ok(loaded)
+    }
+
+    /// Time of the first frame, still renders are taken there
+    pub fn start(&self) -> f64 {
+        self.animation
+            .as_ref()
+            .map_or(0.0, |a| a.frame_range(a.fps).0 as f64 / a.fps)
+    }
+}
+
+/// Reads a scene, refusing it if validation finds errors.
+/// Animated scenes are taken at their first frame.
+pub fn load_scene(path: &Path, variables: &Map<String, Value>) -> Result<LoadedScene, LoadError> {
+    let animated = AnimatedScene::read(path, variables)?;
+    animated.frame(animated.start())
+}
+
+/// Everything validation has to say about a scene that parses
+pub fn check_scene(
+    path: &Path,
+    variables: &Map<String, Value>,
+) -> Result<Vec<Diagnostic>, LoadError> {
+    let animated = AnimatedScene::read(path, variables)?;
+    Ok(animated.at(animated.start())?.scene.validate())
+}
+
+/// Rewrites a scene in the format of the `output` extension.
+/// Includes, variables and references are kept as they are, only the syntax changes.
+pub fn convert_scene(
+    input: &Path,
+    output: &Path,
+    variables: &Map<String, Value>,
+) -> Result<(), LoadError> {
+    let format = SceneFormat::from_path(output)
+        .ok_or_else(|| LoadError::UnknownFormat(output.to_path_buf()))?;
+    let value = read_value(input)?;
+    // catch broken scenes here rather than when the converted file is rendered
+    load_scene(input, variables)?;
+    let text = format
+        .write(&value)
+        .map_err(|err| LoadError::Parse(output.to_path_buf(), err))?;
+    fs::write(output, text).map_err(|err| LoadError::Io(output.to_path_buf(), err))
+}
+
+/// Writes a scene in the format of the file extension
+pub fn save_scene(scene: &Scene, path: &Path) -> Result<(), LoadError> {
+    let format =
+        SceneFormat::from_path(path).ok_or_else(|| LoadError::UnknownFormat(path.to_path_buf()))?;
+    let mut value = serde_json::to_value(scene).expect("scenes always serialize");
+    shorten_f32s(&mut value);
+    let text = format
+        .write(&value)
+        .map_err(|err| LoadError::Parse(path.to_path_buf(), err))?;
+    fs::write(path, text).map_err(|err| LoadError::Io(path.to_path_buf(), err))
+}
+
+/// Colors and albedos are `f32`s, which come out as `0.18000000715255737` once widened.
+/// Numbers that are exactly some `f32` are written the way that `f32` prints instead.
+fn shorten_f32s(value: &mut Value) {
+    match value {
+        Value::Number(n) if n.is_f64() => {
+            let v = n.as_f64().unwrap_or_default();
+            if v as f32 as f64 == v {
+                let short = (v as f32).to_string().parse().unwrap_or(v);
+                if let Some(short) = serde_json::Number::from_f64(short) {
+                    *n = short;
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(shorten_f32s),
+        Value::Object(map) => map.values_mut().for_each(shorten_f32s),
+        _ => {}
+    }
+}
+
+/// Collects the named materials into the scene's `materials` map.
+///
+/// Named materials come from the files listed in `material_libraries`, each a map of
+/// names to materials, and from the scene's own `materials` map, which wins on conflicts.
+/// Library paths are already relative to the file that lists them.
+fn gather_materials(source: &mut SceneSource) -> Result<(), String> {
+    let scene = source
+        .value
+        .as_object_mut()
+        .ok_or("the scene has to be a map")?;
+
+    let mut materials = Map::new();
+    if let Some(libraries) = scene.remove("material_libraries") {
+        let libraries = libraries
+            .as_array()
+            .ok_or("material_libraries: expected a list of files")?;
+        for (i, library) in libraries.iter().enumerate() {
+            let path = library
+                .as_str()
+                .map(PathBuf::from)
+                .ok_or_else(|| format!("material_libraries[{}]: expected a file path", i))?;
+            match read_value(&path).map_err(|err| err.to_string())? {
+                Value::Object(library) => materials.extend(library),
+                _ => return Err(format!("{}: expected a map of materials", path.display())),
+            }
+            source.files.push(path);
+        }
+    }
+    match scene.remove("materials") {
+        Some(Value::Object(own)) => materials.extend(own),
+        Some(_) => return Err("materials: expected a map of names to materials".to_string()),
+        None => {}
+    }
+    scene.insert("materials".to_string(), Value::Object(materials));
+    Ok(())
+}
+
+/// Swaps the material names in elements for the materials in the `materials` map
+fn use_materials(scene: &mut Value) -> Result<(), String> {
+    let scene = scene.as_object_mut().ok_or("the scene has to be a map")?;
+    let materials = match scene.remove("materials") {
+        Some(Value::Object(materials)) => materials,
+        _ => Map::new(),
+    };
+    let elements = match scene.get_mut("elements").and_then(Value::as_array_mut) {
+        Some(elements) => elements,
+        None => return Ok(()),
+    };
+    for (i, element) in elements.iter_mut().enumerate() {
+        let variants = element.as_object_mut().into_iter().flatten();
+        for (variant, fields) in variants {
+            if let Some(material) = fields.get_mut("material") {
+                if let Value::String(name) = material {
+                    *material = materials.get(name.as_str()).cloned().ok_or_else(|| {
+                        format!(
+                            "elements[{}].{}.material: unknown material '{}'",
+                            i, variant, name
+                        )
+                    })?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Texture, relief map, mask and density grid files a scene refers to, found without loading them
+pub fn texture_paths(value: &Value) -> Vec<PathBuf> {
+    fn collect(value: &Value, paths: &mut Vec<PathBuf>) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    match (key.as_str(), value) {
+                        ("Texture", Value::String(path))
+                        | ("texture", Value::String(path))
+                        | ("mask", Value::String(path))
+                        | ("grid", Value::String(path)) => paths.push(PathBuf::from(path)),
+                        _ => collect(value, paths),
+                    }
+                }
+            }
+            Value::Array(values) => values.iter().for_each(|v| collect(v, paths)),
+            _ => {}
+        }
+    }
+
+    let mut paths = vec![];
+    collect(value, &mut paths);
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        conductor::Conductor,
+        entities::{Color, Light, Material},
+        point::Point,
+        vector::Vector3,
+    };
+
+    fn scene() -> Scene {
+        let white = Color::new(1.0, 1.0, 1.0);
+        Scene::builder()
+            .size(64, 48)
+            .fov(60.0)
+            .sphere(
+                Point::new(0.0, 0.0, -5.0),
+                1.0,
+                Material::diffuse(Color::new(1.0, 0.2, 0.2)),
+            )
+            .sphere(
+                Point::new(2.0, 0.0, -5.0),
+                0.5,
+                Material::refractive(white, 1.5, 0.9),
+            )
+            .sphere(
+                Point::new(-2.0, 0.0, -5.0),
+                0.5,
+                Material::conductor(Conductor::Gold),
+            )
+            .sphere(
+                Point::new(0.0, 2.0, -6.0),
+                0.3,
+                Material::emissive(Color::new(1.0, 0.8, 0.6), 4.0),
+            )
+            .plane(
+                Point::new(0.0, -1.0, 0.0),
+                Vector3::new(0.0, -1.0, 0.0),
+                Material::reflective(Color::new(0.2, 0.2, 0.2), 0.3),
+            )
+            // loading normalizes directions again, which may change the last digit
+            .light(Light::directional(Vector3::new(0.0, -1.0, 0.0), white, 3.0))
+            .light(Light::spherical(Point::new(1.0, 3.0, -4.0), white, 500.0))
+            .light(Light::spot(
+                Point::new(0.0, 4.0, -5.0),
+                Vector3::new(0.0, -1.0, 0.0),
+                white,
+                800.0,
+                15.0,
+                25.0,
+            ))
+            .build()
+    }
+
+    #[test]
+    fn saved_scenes_load_back() {
+        let scene = scene();
+        let expected = serde_json::to_value(&scene).unwrap();
+        for extension in &["json", "toml", "yaml", "ron"] {
+            let path = std::env::temp_dir().join(format!(
+                "rtracer-save-{}.{}",
+                std::process::id(),
+                extension
+            ));
+            save_scene(&scene, &path).unwrap();
+            let loaded = load_scene(&path, &Map::new());
+            fs::remove_file(&path).unwrap();
+            let loaded = loaded.unwrap_or_else(|err| panic!("{}: {}", extension, err));
+            assert_eq!(
+                serde_json::to_value(&loaded.scene).unwrap(),
+                expected,
+                "{} round trip",
+                extension
+            );
+        }
+    }
+
+    #[test]
+    fn saved_colors_stay_short() {
+        let path = std::env::temp_dir().join(format!("rtracer-short-{}.json", std::process::id()));
+        save_scene(&scene(), &path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(text.contains("0.18"));
+        assert!(!text.contains("0.18000000715255737"));
+    }
+
+    #[test]
+    fn library_materials_use_scene_variables() {
+        let temp = |name: &str| {
+            std::env::temp_dir().join(format!("rtracer-{}-{}.json", name, std::process::id()))
+        };
+        let (library, scene_path) = (temp("library"), temp("library-scene"));
+        let mut glass = serde_json::to_value(Material::diffuse(Color::new(1.0, 1.0, 1.0))).unwrap();
+        glass["albedo"] = Value::from("$albedo");
+        let mut scene = serde_json::to_value(scene()).unwrap();
+        scene["material_libraries"] = serde_json::json!([library]);
+        scene["variables"] = serde_json::json!({"albedo": 0.5});
+        scene["elements"][0]["Sphere"]["material"] = Value::from("glass");
+        fs::write(&library, serde_json::json!({ "glass": glass }).to_string()).unwrap();
+        fs::write(&scene_path, scene.to_string()).unwrap();
+
+        let loaded = load_scene(&scene_path, &Map::new());
+        fs::remove_file(&library).unwrap();
+        fs::remove_file(&scene_path).unwrap();
+        let loaded = serde_json::to_value(loaded.unwrap().scene).unwrap();
+        assert_eq!(
+            loaded["elements"][0]["Sphere"]["material"]["albedo"],
+            Value::from(0.5)
+        );
+    }
+
+    /// Empty directory for a test's files, gone with the next run of the same test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rtracer-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn includes_are_relative_to_the_including_file() {
+        let dir = temp_dir("includes");
+        let scene = serde_json::to_value(scene()).unwrap();
+        fs::write(dir.join("sub/rig.json"), scene.to_string()).unwrap();
+        fs::write(dir.join("sub/shot.json"), r#"{"include": "rig.json"}"#).unwrap();
+        fs::write(
+            dir.join("main.json"),
+            r#"{"include": ["sub/shot.json"], "width": 32}"#,
+        )
+        .unwrap();
+
+        let loaded = load_scene(&dir.join("main.json"), &Map::new());
+        fs::remove_dir_all(&dir).unwrap();
+        let loaded = loaded.unwrap().scene;
+        assert_eq!(loaded.width, 32);
+        assert_eq!(loaded.elements.len(), 5);
+    }
+
+    #[test]
+    fn libraries_are_relative_to_the_file_naming_them() {
+        let dir = temp_dir("libraries");
+        let glass = serde_json::to_value(Material::refractive(Color::new(1.0, 1.0, 1.0), 1.5, 0.9));
+        let library = serde_json::json!({ "glass": glass.unwrap() });
+        fs::write(dir.join("sub/materials.json"), library.to_string()).unwrap();
+        let mut rig = serde_json::to_value(scene()).unwrap();
+        rig["material_libraries"] = serde_json::json!(["materials.json"]);
+        rig["elements"][0]["Sphere"]["material"] = Value::from("glass");
+        fs::write(dir.join("sub/rig.json"), rig.to_string()).unwrap();
+        fs::write(dir.join("main.json"), r#"{"include": "sub/rig.json"}"#).unwrap();
+
+        let loaded = load_scene(&dir.join("main.json"), &Map::new());
+        fs::remove_dir_all(&dir).unwrap();
+        let loaded = serde_json::to_value(loaded.unwrap().scene).unwrap();
+        let surface = &loaded["elements"][0]["Sphere"]["material"]["surface"];
+        assert!(surface.get("Refractive").is_some(), "{}", surface);
+    }
+
+    #[test]
+    fn include_cycles() {
+        let dir = temp_dir("include-cycles");
+        fs::write(dir.join("self.json"), r#"{"include": "./self.json"}"#).unwrap();
+        fs::write(dir.join("a.json"), r#"{"include": "sub/b.json"}"#).unwrap();
+        fs::write(dir.join("sub/b.json"), r#"{"include": "../sub/../a.json"}"#).unwrap();
+
+        let own = load_scene(&dir.join("self.json"), &Map::new());
+        let round = load_scene(&dir.join("a.json"), &Map::new());
+        fs::remove_dir_all(&dir).unwrap();
+        for result in [own, round] {
+            match result {
+                Err(LoadError::Parse(_, err)) => assert_eq!(err, "includes itself"),
+                Err(err) => panic!("{}", err),
+                Ok(_) => panic!("loaded a cycle"),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_extension() {
+        let path = Path::new("scene.txt");
+        assert!(matches!(
+            save_scene(&scene(), path),
+            Err(LoadError::UnknownFormat(_))
+        ));
+    }
+}
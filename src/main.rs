@@ -16,6 +16,31 @@
 //! 3. Basic shadows
 //! 4. Texturing: using vector products to calculate texture
 //! 5. Reflections: basic implementation with recursive restriction.
+//! 6. Render passes: depth, normal, albedo, direct/indirect light, shadow mask and object id.
+//! 7. Denoising: edge-aware à-trous filter guided by the normal, depth and albedo passes (`--denoise`).
+//! 8. Progressive rendering: jittered samples accumulate until a sample, time or noise budget runs out.
+//! 9. Checkpoints: progressive renders are saved as they go and continue with `--resume`.
+//! 10. Live progress bar and ray statistics, `--stats-json` to keep them.
+//! 11. Truecolor terminal preview of renders.
+//! 12. Interactive terminal scene explorer.
+//! 13. Watch mode: re-render when the scene or its textures change.
+//! 14. Scene files in JSON, TOML, YAML or RON, and a `convert` command between them.
+//! 15. Named materials and material libraries.
+//! 16. Scene serialization and a fluent scene builder.
+//! 17. Scene validation on load and a `check` command.
+//! 18. Scene includes and `$variables` with command line overrides.
+//! 19. Keyframe animation rendered as a frame sequence.
+//! 20. Motion blur from time-sampled rays.
+//! 21. Spot lights with cone falloff and gobo textures.
+//! 22. Emissive materials and a path tracing mode that samples them.
+//! 23. Preetham physical sky with a matching sun.
+//! 24. Exponential fog and homogeneous media inside elements.
+//! 25. Voxel grid volumes rendered with delta tracking.
+//! 26. Normal and bump maps.
+//! 27. Alpha cutouts.
+//! 28. Colored and partial shadows through glass.
+//! 29. Spectral rendering with Cauchy and Sellmeier dispersion.
+//! 30. Conductors with a complex index of refraction.
 //!
 //! ### useful resourses
 //! [Scrathpixel](https://www.scratchapixel.com/index.php?redirect) is fantastic library!
@@ -24,39 +49,319 @@
 //!
 
 use image::DynamicImage;
-use std::{fs::File, path::Path, time::Instant};
+use std::{fs, process, time::Instant};
 
+mod animation;
+mod builder;
+mod camera;
+mod checkpoint;
+mod cli;
+mod conductor;
+mod cutout;
+mod denoise;
 mod entities;
+mod explorer;
 mod fractal;
+mod grid;
+mod loader;
+mod passes;
+mod path_tracing;
 mod point;
+mod preview;
+mod progressive;
+mod relief;
 mod rendering;
+mod sampling;
 mod scene;
+mod sky;
+mod spectral;
+mod stats;
 mod utils;
+mod variables;
+mod validate;
 mod vector;
+mod volume;
+mod watch;
 
+pub use animation::*;
+pub use builder::*;
+pub use camera::*;
+pub use checkpoint::*;
+pub use cli::*;
+pub use conductor::*;
+pub use cutout::*;
+pub use denoise::*;
 pub use entities::*;
+pub use explorer::*;
 pub use fractal::*;
+pub use grid::*;
+pub use loader::*;
+pub use passes::*;
+pub use path_tracing::*;
 pub use point::*;
+pub use preview::*;
+pub use progressive::*;
+pub use relief::*;
 pub use rendering::*;
+pub use sampling::*;
 use scene::Scene;
+pub use sky::*;
+pub use spectral::*;
+pub use stats::*;
 pub use utils::*;
+pub use variables::*;
+pub use validate::*;
 pub use vector::*;
+pub use volume::*;
+pub use watch::*;
 
 pub fn main() {
-    let scene_path = Path::new("scenes/main.json");
-    let scene_file = File::open(scene_path).expect("File not found");
-    let scene: Scene = serde_json::from_reader(scene_file).unwrap();
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", USAGE);
+        return;
+    }
 
-    // Render main scene with 3 spheres and 2 planes image
-    let start = Instant::now();
-    println!("Start ray tracing image render...");
-    let img: DynamicImage = scene.render();
-    save_image(img, &Path::new("output/test_scene.png"));
+    if args.command == Command::Convert {
+        match convert_scene(&args.scene, &args.output, &args.variables) {
+            Ok(_) => print_green(&format!("converted to {:?}", args.output)),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.command == Command::Check {
+        match check_scene(&args.scene, &args.variables) {
+            Ok(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    println!("{}", diagnostic);
+                }
+                if has_errors(&diagnostics) {
+                    process::exit(1);
+                }
+                print_green(&format!("{:?} is fine", args.scene));
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.command == Command::Animate {
+        animate(&args);
+        return;
+    }
+
+    if args.command == Command::Watch {
+        watch(&args.scene, &args.variables, |loaded, timer| {
+            render(&args, &loaded.scene, &loaded.bytes, timer)
+        });
+        return;
+    }
+
+    let mut timer = PhaseTimer::default();
+    let LoadedScene {
+        scene,
+        bytes,
+        warnings,
+    } = match load_scene(&args.scene, &args.variables) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    timer.end("load");
+    for warning in &warnings {
+        println!("{}", warning);
+    }
+
+    if args.command != Command::Render {
+        let result = match args.command {
+            Command::Explore => explore(scene, &args.output),
+            _ => preview(scene),
+        };
+        if let Err(err) = result {
+            eprintln!("{:?} failed: {}", args.command, err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Err(err) = render(&args, &scene, &bytes, timer) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
 
     // // Render fractal image
     // println!("Start fractal image render...");
     // let f = Fractal::default();
     // f.save(&Path::new("output/fractal.png"));
+}
+
+/// Renders every frame of the animation, each one like a still render
+fn animate(args: &Args) {
+    fn fail(err: LoadError) -> ! {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+    let animated = AnimatedScene::read(&args.scene, &args.variables).unwrap_or_else(|err| fail(err));
+    let animation = match &animated.animation {
+        Some(animation) => animation,
+        None => {
+            eprintln!("{:?} has no animation", args.scene);
+            process::exit(1);
+        }
+    };
+    let fps = args.fps.unwrap_or(animation.fps);
+    let (first, last) = args.frames.unwrap_or_else(|| animation.frame_range(fps));
+    let mut failed = 0;
+    for frame in first..=last {
+        let mut timer = PhaseTimer::default();
+        let loaded = animated
+            .frame(frame as f64 / fps)
+            .unwrap_or_else(|err| fail(err));
+        timer.end("load");
+        print_green(&format!("frame {} of {}..{}", frame, first, last));
+        let frame_args = Args {
+            output: frame_path(&args.output, frame),
+            ..args.clone()
+        };
+        // a frame that can't render doesn't stop the rest of the sequence
+        if let Err(err) = render(&frame_args, &loaded.scene, &loaded.bytes, timer) {
+            eprintln!("frame {}: {}", frame, err);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        eprintln!("{} of {} frames failed", failed, last - first + 1);
+        process::exit(1);
+    }
+}
+
+/// Renders the scene with everything asked for on the command line and saves the results
+fn render(
+    args: &Args,
+    scene: &Scene,
+    scene_bytes: &[u8],
+    mut timer: PhaseTimer,
+) -> Result<(), String> {
+    let start = Instant::now();
+    let mut passes = scene.passes.clone();
+    passes.extend(args.passes.iter().filter(|p| !scene.passes.contains(p)));
+
+    println!("Start ray tracing image render...");
+    let denoiser = args.denoiser(scene.denoise);
+    // a single path, wavelength or collision test per pixel is mostly noise
+    let noisy = scene.path_tracing.is_some()
+        || scene.spectral.is_some()
+        || !scene.volume_grids.is_empty();
+    let progressive = args.progressive(scene.progressive.or(noisy.then(Progressive::default)));
+    let tiles = ViewBlock::tiles(scene.width, scene.height).len() as u64;
+    let progress = |total| {
+        if args.quiet {
+            Progress::hidden()
+        } else {
+            Progress::new(total)
+        }
+    };
+
+    if passes.is_empty() && denoiser.is_none() && progressive.is_none() {
+        let mut progress = progress(tiles);
+        let img: DynamicImage = scene.render(&mut progress);
+        progress.finish();
+        timer.end("render");
+        save_image(img, &args.output);
+        timer.end("save");
+    } else {
+        let buffers = match progressive {
+            Some(settings) => {
+                let hash = scene_hash(scene_bytes);
+                let checkpoint = args
+                    .checkpoint
+                    .clone()
+                    .unwrap_or_else(|| checkpoint_path(&args.output));
+                let mut renderer = if args.resume {
+                    match Checkpoint::load(&checkpoint, hash, scene.width, scene.height) {
+                        Ok(c) => ProgressiveRenderer::resume(scene, settings, &passes, c),
+                        Err(err) => {
+                            return Err(format!("can't resume from {:?}: {}", checkpoint, err))
+                        }
+                    }
+                } else {
+                    ProgressiveRenderer::new(scene, settings, &passes)
+                };
+                let save_checkpoint = |r: &ProgressiveRenderer| {
+                    if let Err(err) = Checkpoint::save(r, hash, &checkpoint) {
+                        println!("failed to save checkpoint {:?}", err);
+                    }
+                };
+                let mut progress = if args.quiet {
+                    Progress::hidden()
+                } else {
+                    renderer.progress()
+                };
+                renderer.run(&mut progress, |r| {
+                    save_image(r.accumulator.resolve().beauty_image(), &args.output);
+                    save_checkpoint(r);
+                });
+                progress.finish();
+                // keep the final state too, so the render can be refined later
+                save_checkpoint(&renderer);
+                println!(
+                    "{:.1} samples per pixel on average",
+                    renderer.accumulator.average_samples()
+                );
+                renderer.accumulator.resolve()
+            }
+            None => {
+                let mut progress = progress(tiles);
+                let buffers = scene.render_passes(&passes, &mut progress);
+                progress.finish();
+                buffers
+            }
+        };
+        timer.end("render");
+        let mut img = buffers.beauty_image();
+        if let Some(denoiser) = denoiser {
+            let colors = denoiser.denoise(&buffers);
+            let denoised = colors_to_image(scene.width, scene.height, &colors);
+            timer.end("denoise");
+            if args.denoise_compare {
+                save_image(
+                    side_by_side(&img, &denoised),
+                    &pass_path(&args.output, "compare"),
+                );
+            }
+            img = denoised;
+        }
+        save_image(img, &args.output);
+        buffers.save(&passes, &args.output, args.pass_format);
+        timer.end("save");
+    }
+
+    let report = StatsReport::new(&scene.stats, timer.phases);
+    if !args.quiet {
+        report.print();
+    }
+    if let Some(path) = &args.stats_json {
+        match fs::write(path, serde_json::to_string_pretty(&report).unwrap()) {
+            Ok(_) => print_green(&format!("statistics saved as {:?}", path)),
+            Err(err) => println!("failed to save statistics {:?}", err),
+        }
+    }
 
     println!("Elapsed: {:?}", start.elapsed());
+    Ok(())
 }
@@ -0,0 +1,374 @@
+//! Arbitrary output variables (AOVs): extra render passes written next to the beauty image.
+//!
+//! Every primary ray fills a [`PassSample`] with the data compositing and denoising need.
+//! The passes can be saved either as separate PNG files (`test_scene.depth.png`, ...)
+//! or as layers of a single OpenEXR file keeping the full float precision.
+use crate::{entities::Color, rendering::BLACK, vector::Vector3};
+use exr::image::Layers;
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, Image, ImageAttributes, IntegerBounds, Layer,
+    LayerAttributes, SmallVec, Vec2, WritableImage,
+};
+use image::{DynamicImage, GenericImage, ImageBuffer, Luma, Rgb};
+use serde_derive::{Deserialize, Serialize};
+
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    /// Camera space depth of the primary hit
+    Depth,
+    /// World space surface normal
+    Normal,
+    /// Surface color before any lighting
+    Albedo,
+    /// Light arriving straight from the light sources
+    Direct,
+    /// Light arriving through reflections and refractions
+    Indirect,
+    /// Fraction of the lights occluded at the primary hit
+    Shadow,
+    /// Index of the hit element in `Scene::elements`, starting from 1. Background is 0.
+    ObjectId,
+}
+
+pub const ALL_PASSES: [Pass; 7] = [
+    Pass::Depth,
+    Pass::Normal,
+    Pass::Albedo,
+    Pass::Direct,
+    Pass::Indirect,
+    Pass::Shadow,
+    Pass::ObjectId,
+];
+
+impl Pass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pass::Depth => "depth",
+            Pass::Normal => "normal",
+            Pass::Albedo => "albedo",
+            Pass::Direct => "direct",
+            Pass::Indirect => "indirect",
+            Pass::Shadow => "shadow",
+            Pass::ObjectId => "id",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Pass> {
+        ALL_PASSES
+            .iter()
+            .find(|p| p.name() == name.to_lowercase())
+            .copied()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PassFormat {
+    /// One PNG per pass
+    Png,
+    /// Single multi-layer EXR including the beauty image
+    Exr,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PassSample {
+    pub depth: f32,
+    pub normal: Vector3,
+    pub albedo: Color,
+    pub direct: Color,
+    pub indirect: Color,
+    pub shadow: f32,
+    pub object_id: u32,
+}
+
+impl Default for PassSample {
+    /// What a ray that missed everything sees
+    fn default() -> PassSample {
+        PassSample {
+            depth: f32::INFINITY,
+            normal: Vector3::zero(),
+            albedo: BLACK,
+            direct: BLACK,
+            indirect: BLACK,
+            shadow: 0.0,
+            object_id: 0,
+        }
+    }
+}
+
+impl PassSample {
+    pub fn beauty(&self) -> Color {
+        self.direct + self.indirect
+    }
+}
+
+pub struct PassBuffers {
+    pub width: u32,
+    pub height: u32,
+    samples: Vec<PassSample>,
+}
+
+impl PassBuffers {
+    pub fn new(width: u32, height: u32) -> PassBuffers {
+        PassBuffers {
+            width,
+            height,
+            samples: vec![PassSample::default(); (width * height) as usize],
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> &PassSample {
+        &self.samples[(y * self.width + x) as usize]
+    }
+
+    pub fn samples(&self) -> &[PassSample] {
+        &self.samples
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, sample: PassSample) {
+        self.samples[(y * self.width + x) as usize] = sample;
+    }
+
+    pub fn beauty_image(&self) -> DynamicImage {
+        let mut image = DynamicImage::new_rgb8(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                image.put_pixel(x, y, self.get(x, y).beauty().to_rgba());
+            }
+        }
+        image
+    }
+
+    /// Largest finite depth, used to normalize the depth preview
+    fn max_depth(&self) -> f32 {
+        self.samples
+            .iter()
+            .map(|s| s.depth)
+            .filter(|d| d.is_finite())
+            .fold(0.0, f32::max)
+    }
+
+    /// 8 or 16 bit preview of a pass suitable for PNG.
+    /// Depth is normalized to the farthest hit and object ids are stored verbatim in 16 bits.
+    pub fn to_image(&self, pass: Pass) -> DynamicImage {
+        let max_depth = self.max_depth().max(f32::EPSILON);
+        match pass {
+            Pass::Depth => {
+                DynamicImage::ImageLuma16(ImageBuffer::from_fn(self.width, self.height, |x, y| {
+                    let d = self.get(x, y).depth;
+                    let v = if d.is_finite() {
+                        1.0 - d / max_depth
+                    } else {
+                        0.0
+                    };
+                    Luma([(v * u16::MAX as f32) as u16])
+                }))
+            }
+            Pass::ObjectId => {
+                DynamicImage::ImageLuma16(ImageBuffer::from_fn(self.width, self.height, |x, y| {
+                    Luma([self.get(x, y).object_id.min(u16::MAX as u32) as u16])
+                }))
+            }
+            Pass::Shadow => {
+                DynamicImage::ImageLuma8(ImageBuffer::from_fn(self.width, self.height, |x, y| {
+                    Luma([(self.get(x, y).shadow * 255.0) as u8])
+                }))
+            }
+            Pass::Normal => {
+                DynamicImage::ImageRgb8(ImageBuffer::from_fn(self.width, self.height, |x, y| {
+                    let n = self.get(x, y).normal;
+                    let encode = |v: f64| ((v * 0.5 + 0.5) * 255.0) as u8;
+                    if n.norm() == 0.0 {
+                        Rgb([0, 0, 0])
+                    } else {
+                        Rgb([encode(n.x), encode(n.y), encode(n.z)])
+                    }
+                }))
+            }
+            Pass::Albedo | Pass::Direct | Pass::Indirect => {
+                DynamicImage::ImageRgb8(ImageBuffer::from_fn(self.width, self.height, |x, y| {
+                    let c = self.color(pass, self.get(x, y)).to_rgba();
+                    Rgb([c.0[0], c.0[1], c.0[2]])
+                }))
+            }
+        }
+    }
+
+    fn color(&self, pass: Pass, sample: &PassSample) -> Color {
+        match pass {
+            Pass::Albedo => sample.albedo,
+            Pass::Direct => sample.direct,
+            Pass::Indirect => sample.indirect,
+            _ => sample.beauty(),
+        }
+    }
+
+    /// Saves passes next to `beauty_path`, e.g. `output/test_scene.png` gives
+    /// `output/test_scene.depth.png` or `output/test_scene.exr`.
+    pub fn save(&self, passes: &[Pass], beauty_path: &Path, format: PassFormat) {
+        match format {
+            PassFormat::Png => {
+                for pass in passes {
+                    crate::save_image(self.to_image(*pass), &pass_path(beauty_path, pass.name()));
+                }
+            }
+            PassFormat::Exr => {
+                let path = beauty_path.with_extension("exr");
+                crate::print_italic(&format!("saving layers as {:?}...", path));
+                match self.to_exr(passes).write().to_file(&path) {
+                    Ok(_) => crate::print_green("success!"),
+                    Err(err) => println!("failed to save {:?}", err),
+                }
+            }
+        }
+    }
+
+    fn to_exr(&self, passes: &[Pass]) -> Image<Layers<AnyChannels<FlatSamples>>> {
+        let size = Vec2(self.width as usize, self.height as usize);
+        let channel = |name: &str, f: &dyn Fn(&PassSample) -> f32| {
+            AnyChannel::new(name, FlatSamples::F32(self.samples.iter().map(f).collect()))
+        };
+        let rgb = |f: &dyn Fn(&PassSample) -> Color| {
+            vec![
+                channel("R", &|s| f(s).red),
+                channel("G", &|s| f(s).green),
+                channel("B", &|s| f(s).blue),
+            ]
+        };
+        let layer = |name: &str, channels: Vec<AnyChannel<FlatSamples>>| {
+            Layer::new(
+                size,
+                LayerAttributes::named(name),
+                Encoding::FAST_LOSSLESS,
+                AnyChannels::sort(SmallVec::from_vec(channels)),
+            )
+        };
+
+        // the beauty image goes first, so viewers show it by default
+        let mut layers = vec![layer("beauty", rgb(&|s| s.beauty()))];
+        for pass in passes {
+            let channels = match pass {
+                Pass::Depth => vec![channel("Z", &|s| s.depth)],
+                Pass::Shadow => vec![channel("Y", &|s| s.shadow)],
+                Pass::ObjectId => vec![AnyChannel::new(
+                    "id",
+                    FlatSamples::U32(self.samples.iter().map(|s| s.object_id).collect()),
+                )],
+                Pass::Normal => vec![
+                    channel("X", &|s| s.normal.x as f32),
+                    channel("Y", &|s| s.normal.y as f32),
+                    channel("Z", &|s| s.normal.z as f32),
+                ],
+                _ => rgb(&|s| self.color(*pass, s)),
+            };
+            layers.push(layer(pass.name(), channels));
+        }
+
+        Image::from_layers(
+            ImageAttributes::new(IntegerBounds::from_dimensions(size)),
+            layers,
+        )
+    }
+}
+
+/// `output/test_scene.png` + `depth` => `output/test_scene.depth.png`
+pub fn pass_path(beauty_path: &Path, suffix: &str) -> PathBuf {
+    let stem = beauty_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    beauty_path.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exr::prelude::read_all_flat_layers_from_file;
+
+    /// Three pixels: a near hit, a far hit and the background
+    fn buffers() -> PassBuffers {
+        let mut buffers = PassBuffers::new(3, 1);
+        let hit = |depth: f32, object_id: u32| PassSample {
+            depth,
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            albedo: Color::new(1.0, 0.5, 0.25),
+            object_id,
+            ..PassSample::default()
+        };
+        buffers.set(0, 0, hit(1.0, 3));
+        buffers.set(1, 0, hit(2.0, 300));
+        buffers
+    }
+
+    #[test]
+    fn set_and_get() {
+        let buffers = buffers();
+        assert_eq!(buffers.get(1, 0).object_id, 300);
+        assert_eq!(buffers.get(2, 0).depth, f32::INFINITY);
+        assert_eq!(buffers.samples().len(), 3);
+    }
+
+    #[test]
+    fn png_channels() {
+        let buffers = buffers();
+        let depth = buffers.to_image(Pass::Depth).into_luma16();
+        // nearer is brighter, the farthest hit and the background are black
+        assert_eq!(depth.get_pixel(0, 0).0, [u16::MAX / 2]);
+        assert_eq!(depth.get_pixel(1, 0).0, [0]);
+        assert_eq!(depth.get_pixel(2, 0).0, [0]);
+
+        let ids = buffers.to_image(Pass::ObjectId).into_luma16();
+        let ids: Vec<u16> = ids.pixels().map(|p| p.0[0]).collect();
+        assert_eq!(ids, vec![3, 300, 0]);
+
+        let normals = buffers.to_image(Pass::Normal).into_rgb8();
+        assert_eq!(normals.get_pixel(0, 0).0, [127, 127, 255]);
+        assert_eq!(normals.get_pixel(2, 0).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn exr_layers() {
+        let path = std::env::temp_dir().join(format!("rtracer-passes-{}.exr", std::process::id()));
+        let passes = [Pass::Depth, Pass::Normal, Pass::ObjectId];
+        buffers().to_exr(&passes).write().to_file(&path).unwrap();
+        let image = read_all_flat_layers_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let image = image.unwrap();
+
+        let layer = |name: &str| {
+            image
+                .layer_data
+                .iter()
+                .find(|l| {
+                    l.attributes.layer_name.as_ref().map(|n| n.to_string())
+                        == Some(name.to_string())
+                })
+                .unwrap_or_else(|| panic!("no {} layer", name))
+        };
+        let channels = |name: &str| -> Vec<(String, FlatSamples)> {
+            layer(name)
+                .channel_data
+                .list
+                .iter()
+                .map(|c| (c.name.to_string(), c.sample_data.clone()))
+                .collect()
+        };
+        assert_eq!(image.layer_data.len(), 4);
+        assert_eq!(
+            channels("depth"),
+            vec![(
+                "Z".to_string(),
+                FlatSamples::F32(vec![1.0, 2.0, f32::INFINITY])
+            )]
+        );
+        let normal: Vec<String> = channels("normal").into_iter().map(|(n, _)| n).collect();
+        assert_eq!(normal, vec!["X", "Y", "Z"]);
+        assert_eq!(
+            channels("id"),
+            vec![("id".to_string(), FlatSamples::U32(vec![3, 300, 0]))]
+        );
+        let beauty: Vec<String> = channels("beauty").into_iter().map(|(n, _)| n).collect();
+        assert_eq!(beauty, vec!["B", "G", "R"]);
+    }
+}
@@ -0,0 +1,456 @@
+//! Path tracing: rays bounce off diffuse surfaces in random directions too, not only off
+//! mirrors and through glass, so light from emissive elements and from other surfaces
+//! reaches everything. One path per sample is noisy, the progressive renderer averages
+//! as many as it is given.
+use crate::{
+    entities::{Color, Element, Intersection, SurfaceType},
+    passes::{Pass, PassSample},
+    point::Point,
+    rendering::{Intersectable, Ray, BLACK},
+    sampling::Rng,
+    scene::Scene,
+    vector::Vector3,
+    volume::BOUNDARY_STEP,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use std::f64::consts::PI;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct PathTracing {
+    /// Bounces after the camera ray before a path gives up
+    pub max_bounces: u32,
+}
+
+impl Default for PathTracing {
+    fn default() -> PathTracing {
+        PathTracing { max_bounces: 5 }
+    }
+}
+
+/// Where a path goes after a hit and how much of what it finds there gets back
+struct Bounce {
+    ray: Ray,
+    weight: Color,
+    /// Mirror and glass bounces can't aim at emitters, so whatever emission they find counts
+    specular: bool,
+}
+
+impl Scene {
+    /// Same as [`Scene::sample_passes`], with the light gathered along one random path
+    pub fn sample_path(
+        &self,
+        ray: &Ray,
+        settings: PathTracing,
+        passes: &[Pass],
+        rng: &mut Rng,
+    ) -> PassSample {
+        let first = match self.trace(ray) {
+            Some(i) => i,
+            None => {
+                let background = self.background(ray);
+                return PassSample {
+                    direct: match self.media_along(ray, f64::INFINITY) {
+                        Some(segment) => segment.apply(background),
+                        None => background,
+                    },
+                    ..PassSample::default()
+                };
+            }
+        };
+        let hit = ray.origin + (ray.direction * first.distance);
+        let local = first.local(hit);
+        let normal = first.shading_normal(hit);
+        let mut sample = PassSample {
+            depth: (first.distance * ray.direction.dot(&self.camera.direction)) as f32,
+            normal,
+            albedo: first
+                .element
+                .material()
+                .coloration
+                .color(&first.element.texture_coords(&local)),
+            direct: BLACK,
+            indirect: BLACK,
+            shadow: self.shadow_pass(passes, hit, normal, ray.time),
+            object_id: self.object_id(first.element),
+        };
+
+        let mut ray = *ray;
+        let mut intersection = first;
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        // the diffuse hit the path last left from, which aimed at emitters already
+        let mut diffuse_hit: Option<(Point, &Element)> = None;
+        for bounce in 0..=settings.max_bounces {
+            self.stats.depth_reached(bounce);
+            if let Some(segment) = self.media_along(&ray, intersection.distance) {
+                sample.indirect = sample.indirect + throughput * segment.inscattered;
+                throughput = throughput * segment.transmittance;
+            }
+            let (light, next) = self.scatter(&ray, &intersection, diffuse_hit, rng);
+            if bounce == 0 {
+                sample.direct = throughput * light;
+            } else {
+                sample.indirect = sample.indirect + throughput * light;
+            }
+            let next = match next {
+                Some(next) if bounce < settings.max_bounces => next,
+                _ => break,
+            };
+            throughput = throughput * next.weight;
+            diffuse_hit = if next.specular {
+                None
+            } else {
+                let hit = ray.origin + (ray.direction * intersection.distance);
+                Some((hit, intersection.element))
+            };
+            ray = next.ray;
+            intersection = match self.trace(&ray) {
+                Some(i) => i,
+                None => {
+                    let background = self.background(&ray);
+                    let seen = match self.media_along(&ray, f64::INFINITY) {
+                        Some(segment) => segment.apply(background),
+                        None => background,
+                    };
+                    sample.indirect = sample.indirect + throughput * seen;
+                    break;
+                }
+            };
+        }
+        sample
+    }
+
+    /// Light leaving the hit towards the ray's origin that doesn't come from further
+    /// down the path, and where the path goes next. `diffuse_hit` is where the ray left
+    /// a diffuse surface, `None` after the camera, a mirror or glass.
+    fn scatter(
+        &self,
+        ray: &Ray,
+        intersection: &Intersection,
+        diffuse_hit: Option<(Point, &Element)>,
+        rng: &mut Rng,
+    ) -> (Color, Option<Bounce>) {
+        let hit = ray.origin + (ray.direction * intersection.distance);
+        let local = intersection.local(hit);
+        let normal = intersection.shading_normal(hit);
+        let coords = intersection.element.texture_coords(&local);
+        let material = intersection.element.material();
+
+        // emitters the previous diffuse hit aimed at already lit it
+        let mut light = BLACK;
+        let aimed_at = diffuse_hit.is_some_and(|(point, element)| {
+            aims_at(element, point, intersection.element, ray.time)
+        });
+        if !aimed_at {
+            light = material.emitted(&coords);
+        }
+
+        let reflect = |ray_direction| {
+            self.stats.reflection_ray();
+            Ray {
+                time: ray.time,
+                wavelength: ray.wavelength,
+                ..Ray::create_reflection(normal, ray_direction, hit, self.shadow_bias)
+            }
+        };
+        let bounce = match material.surface {
+            SurfaceType::Reflective { reflectivity }
+                if self.effects.reflections && rng.next_f32() < reflectivity =>
+            {
+                Bounce {
+                    ray: reflect(ray.direction),
+                    weight: Color::new(1.0, 1.0, 1.0),
+                    specular: true,
+                }
+            }
+            SurfaceType::Conductor(metal) if self.effects.reflections => Bounce {
+                ray: reflect(ray.direction),
+                weight: metal.fresnel(ray.direction, normal),
+                specular: true,
+            },
+            SurfaceType::Refractive {
+                index,
+                transparency,
+            } if self.effects.refractions => {
+                let index = index.at(ray.wavelength);
+                let kr = self.fresnel(ray.direction, normal, index) as f32;
+                let transmission = if rng.next_f32() < kr {
+                    None
+                } else {
+                    Ray::create_transmission(normal, ray.direction, hit, self.shadow_bias, index)
+                };
+                let next = match transmission {
+                    Some(transmission) => {
+                        self.stats.refraction_ray();
+                        Ray {
+                            time: ray.time,
+                            wavelength: ray.wavelength,
+                            ..transmission
+                        }
+                    }
+                    None if self.effects.reflections => reflect(ray.direction),
+                    None => return (light, None),
+                };
+                Bounce {
+                    ray: next,
+                    weight: material.coloration.color(&coords) * transparency,
+                    specular: true,
+                }
+            }
+            _ => {
+                // light the side the ray came from
+                let normal = if normal.dot(&ray.direction) > 0.0 {
+                    -normal
+                } else {
+                    normal
+                };
+                light = light
+                    + self.shade_diffuse(intersection, hit, normal, ray.time)
+                    + self.emitter_light(intersection, hit, normal, ray.time, rng);
+                self.stats.reflection_ray();
+                Bounce {
+                    ray: Ray {
+                        origin: hit + (normal * self.shadow_bias),
+                        direction: cosine_direction(normal, rng),
+                        time: ray.time,
+                        wavelength: ray.wavelength,
+                    },
+                    // the cosine and 1/pi of the diffuse reflectance cancel with the
+                    // probability of picking the direction
+                    weight: material.coloration.color(&coords) * material.albedo,
+                    specular: false,
+                }
+            }
+        };
+        (light, Some(bounce))
+    }
+
+    /// Light reaching a diffuse hit straight from emissive spheres, through one random
+    /// point on each of them
+    fn emitter_light(
+        &self,
+        intersection: &Intersection,
+        hit_point: Point,
+        surface_normal: Vector3,
+        time: f64,
+        rng: &mut Rng,
+    ) -> Color {
+        let material = intersection.element.material();
+        let reflectance = material.coloration.color(
+            &intersection
+                .element
+                .texture_coords(&intersection.local(hit_point)),
+        ) * (material.albedo / std::f32::consts::PI);
+
+        let mut color = BLACK;
+        for element in &self.elements {
+            let sphere = match element {
+                Element::Sphere(s) if aims_at(intersection.element, hit_point, element, time) => s,
+                _ => continue,
+            };
+            let to_center = (sphere.center + element.offset_at(time)) - hit_point;
+            let radius2 = sphere.radius * sphere.radius;
+            // pick a direction in the cone the sphere covers, all equally likely
+            let cos_max = (1.0 - radius2 / to_center.norm()).sqrt();
+            let cos_theta = 1.0 - rng.next_f64() * (1.0 - cos_max);
+            let direction = cone_direction(to_center.normalize(), cos_theta, rng);
+            let cos_surface = surface_normal.dot(&direction);
+            if cos_surface <= 0.0 {
+                continue;
+            }
+
+            let origin = hit_point + (surface_normal * self.shadow_bias);
+            let shadow_ray = Ray {
+                origin,
+                direction,
+                time,
+                wavelength: None,
+            };
+            let light = match element.intersect_opaque(&shadow_ray) {
+                Some(distance) => Intersection::new(distance, element, element.offset_at(time)),
+                None => continue,
+            };
+            // through glass and media like the scene's lights, stopping short of the emitter
+            let reaching =
+                self.shadow_transmittance(origin, direction, light.distance - BOUNDARY_STEP, time);
+            if reaching == BLACK {
+                continue;
+            }
+            let light_hit = origin + (direction * light.distance);
+            let emitted = element
+                .material()
+                .emitted(&element.texture_coords(&light.local(light_hit)));
+            let pdf = 1.0 / (2.0 * PI * (1.0 - cos_max));
+            color = color + emitted * reflectance * reaching * (cos_surface / pdf) as f32;
+        }
+        color
+    }
+}
+
+/// Whether a diffuse hit at `hit_point` on `from` aims at `emitter` for its light. Emissive
+/// spheres are, unless the hit is on or inside them. Planes are infinite, their light is
+/// only found by bouncing into them, and so is the light of a sphere seen from inside.
+fn aims_at(from: &Element, hit_point: Point, emitter: &Element, time: f64) -> bool {
+    match emitter {
+        Element::Sphere(s) if s.material.emission.is_some() => {
+            let to_center = (s.center + emitter.offset_at(time)) - hit_point;
+            !std::ptr::eq(from, emitter) && to_center.norm() > s.radius * s.radius
+        }
+        _ => false,
+    }
+}
+
+/// Two unit vectors perpendicular to `w` and each other
+fn basis(w: Vector3) -> (Vector3, Vector3) {
+    let a = if w.x.abs() > 0.9 {
+        Vector3::new(0.0, 1.0, 0.0)
+    } else {
+        Vector3::new(1.0, 0.0, 0.0)
+    };
+    let u = a.cross(&w).normalize();
+    (u, w.cross(&u))
+}
+
+/// Random direction at angle `acos(cos_theta)` from `axis`
+fn cone_direction(axis: Vector3, cos_theta: f64, rng: &mut Rng) -> Vector3 {
+    let (u, v) = basis(axis);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.next_f64();
+    u * (phi.cos() * sin_theta) + v * (phi.sin() * sin_theta) + axis * cos_theta
+}
+
+/// Random direction around `normal`, more likely the closer it is to the normal
+fn cosine_direction(normal: Vector3, rng: &mut Rng) -> Vector3 {
+    let cos_theta = (1.0 - rng.next_f64()).sqrt();
+    cone_direction(normal, cos_theta, rng)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::lit_sphere,
+        entities::{Material, Sphere},
+        rendering::WHITE,
+    };
+
+    /// Light from a glowing sphere above the test sphere of `material` reaching the floor
+    /// under it
+    fn light_under(material: Material) -> Color {
+        let scene = lit_sphere(material)
+            .plane(
+                Point::new(0.0, -2.0, -5.0),
+                Vector3::new(0.0, -1.0, 0.0),
+                Material::diffuse(WHITE),
+            )
+            .sphere(
+                Point::new(0.0, 5.0, -5.0),
+                1.0,
+                Material::emissive(WHITE, 10.0),
+            )
+            .build();
+        let floor = Intersection::new(0.0, &scene.elements[1], Vector3::new(0.0, 0.0, 0.0));
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        let hit = Point::new(0.0, -2.0, -5.0);
+        scene.emitter_light(&floor, hit, up, 0.0, &mut Rng::new(1))
+    }
+
+    #[test]
+    fn emitters_shine_through_glass() {
+        let clear = light_under(Material::refractive(WHITE, 1.5, 1.0));
+        let red_glass = Material::refractive(Color::new(1.0, 0.0, 0.0), 1.5, 1.0);
+        let tinted = light_under(red_glass);
+        assert!(clear.red > 0.0);
+        assert!(
+            (tinted.red - clear.red).abs() < 1e-4 * clear.red,
+            "{:?}",
+            tinted
+        );
+        assert_eq!((tinted.green, tinted.blue), (0.0, 0.0));
+    }
+
+    #[test]
+    fn opaque_elements_block_emitters() {
+        assert_eq!(light_under(Material::diffuse(WHITE)), BLACK);
+    }
+
+    fn glowing(center: Point, radius: f64) -> Element {
+        Element::Sphere(Sphere {
+            center,
+            radius,
+            material: Material::emissive(WHITE, 2.0),
+            motion: None,
+        })
+    }
+
+    #[test]
+    fn only_spheres_seen_from_outside_are_aimed_at() {
+        let scene = Scene::builder()
+            .element(glowing(Point::new(0.0, 0.0, -5.0), 2.0))
+            .plane(
+                Point::new(0.0, -3.0, 0.0),
+                Vector3::new(0.0, -1.0, 0.0),
+                Material::emissive(WHITE, 2.0),
+            )
+            .build();
+        let (sphere, plane) = (&scene.elements[0], &scene.elements[1]);
+        assert!(aims_at(plane, Point::new(0.0, -3.0, -5.0), sphere, 0.0));
+        assert!(!aims_at(plane, Point::new(0.0, -1.0, -5.0), sphere, 0.0));
+        assert!(!aims_at(sphere, Point::new(0.0, 2.0, -5.0), sphere, 0.0));
+        assert!(!aims_at(sphere, Point::new(0.0, 0.0, -5.0), plane, 0.0));
+    }
+
+    #[test]
+    fn emission_counts_unless_the_last_diffuse_hit_aimed_at_it() {
+        // no lights, all a hit on the emitter gives off is its own glow
+        let scene = Scene::builder()
+            .element(glowing(Point::new(0.0, 0.0, -5.0), 2.0))
+            .sphere(Point::new(0.0, 0.0, -5.0), 0.5, Material::diffuse(WHITE))
+            .plane(
+                Point::new(0.0, -3.0, 0.0),
+                Vector3::new(0.0, -1.0, 0.0),
+                Material::diffuse(WHITE),
+            )
+            .build();
+        let ray = Ray {
+            origin: Point::new(0.0, 0.0, -5.5),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            time: 0.0,
+            wavelength: None,
+        };
+        let wall = Intersection::new(1.5, &scene.elements[0], Vector3::zero());
+        let glow = Color::new(2.0, 2.0, 2.0);
+        let mut rng = Rng::new(1);
+        let mut light = |diffuse_hit| scene.scatter(&ray, &wall, diffuse_hit, &mut rng).0;
+        assert_eq!(light(None), glow);
+        let inside = Some((ray.origin, &scene.elements[1]));
+        assert_eq!(light(inside), glow);
+        let floor = Some((Point::new(0.0, -3.0, -5.0), &scene.elements[2]));
+        assert_eq!(light(floor), BLACK);
+    }
+
+    #[test]
+    fn emitters_light_what_is_inside_them() {
+        let scene = Scene::builder()
+            .element(glowing(Point::zero(), 100.0))
+            .sphere(Point::new(0.0, 0.0, -5.0), 1.0, Material::diffuse(WHITE))
+            .build();
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            time: 0.0,
+            wavelength: None,
+        };
+        let mut rng = Rng::new(1);
+        let lit = (0..16)
+            .map(|_| {
+                scene
+                    .sample_path(&ray, PathTracing::default(), &[], &mut rng)
+                    .indirect
+                    .red
+            })
+            .sum::<f32>();
+        assert!(lit > 0.0);
+    }
+}
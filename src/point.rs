@@ -96,10 +96,6 @@ impl Zero for Point {
     fn is_zero(&self) -> bool {
         let Point { x, y, z } = self;
 
-        if *x == 0f64 || *y == 0f64 || *z == 0f64 {
-            true
-        } else {
-            false
-        }
+        *x == 0f64 || *y == 0f64 || *z == 0f64
     }
 }
@@ -0,0 +1,128 @@
+//! Truecolor preview of a render right in the terminal, handy over SSH.
+//!
+//! Every character cell shows two pixels: the upper half block `▀` is painted with the
+//! foreground color and the lower half with the background color, so pixels come out
+//! roughly square.
+use crate::{scene::Scene, stats::Progress};
+use image::{DynamicImage, GenericImageView, Rgba};
+use termion::{
+    clear, color, cursor, event::Key, input::TermRead, raw::IntoRawMode, screen::AlternateScreen,
+    style,
+};
+
+use std::{
+    io::{self, Write},
+    thread,
+    time::Duration,
+};
+
+/// How often the terminal is polled for key presses and size changes
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Largest image size with the aspect ratio of `width` x `height` that fits
+/// `columns` x `rows` cells, keeping the last row free for the status line.
+pub fn fit_to_terminal(width: u32, height: u32, columns: u16, rows: u16) -> (u32, u32) {
+    let max_width = columns as f64;
+    let max_height = (rows.saturating_sub(1) as f64) * 2.0;
+    let scale = (max_width / width as f64).min(max_height / height as f64);
+    (
+        ((width as f64 * scale) as u32).max(1),
+        ((height as f64 * scale) as u32).max(1),
+    )
+}
+
+/// Paints `image` with half blocks, horizontally centered in a `columns` wide terminal
+pub fn draw_image<W: Write>(out: &mut W, image: &DynamicImage, columns: u16) -> io::Result<()> {
+    let left = (columns as u32).saturating_sub(image.width()) / 2;
+    for row in 0..image.height().div_ceil(2) {
+        write!(out, "{}", cursor::Goto(left as u16 + 1, row as u16 + 1))?;
+        for x in 0..image.width() {
+            let top = image.get_pixel(x, row * 2);
+            let bottom = if row * 2 + 1 < image.height() {
+                image.get_pixel(x, row * 2 + 1)
+            } else {
+                Rgba([0, 0, 0, 0])
+            };
+            write!(
+                out,
+                "{}{}▀",
+                color::Fg(color::Rgb(top.0[0], top.0[1], top.0[2])),
+                color::Bg(color::Rgb(bottom.0[0], bottom.0[1], bottom.0[2])),
+            )?;
+        }
+        write!(
+            out,
+            "{}{}",
+            color::Fg(color::Reset),
+            color::Bg(color::Reset)
+        )?;
+    }
+    Ok(())
+}
+
+/// Status text on the last terminal row, cut to the terminal width so it never scrolls
+pub fn draw_status<W: Write>(
+    out: &mut W,
+    (columns, rows): (u16, u16),
+    text: &str,
+) -> io::Result<()> {
+    let text: String = text.chars().take(columns as usize).collect();
+    write!(
+        out,
+        "{}{}{}{}{}",
+        cursor::Goto(1, rows),
+        clear::CurrentLine,
+        style::Invert,
+        text,
+        style::Reset
+    )
+}
+
+/// Renders the scene sized to the terminal until `q`, `Esc` or `Ctrl-C`,
+/// rendering again whenever the terminal gets resized.
+pub fn preview(mut scene: Scene) -> io::Result<()> {
+    if !termion::is_tty(&io::stdout()) {
+        return Err(io::Error::other("the preview needs a terminal"));
+    }
+    let mut keys = termion::async_stdin().keys();
+    let mut screen = AlternateScreen::from(io::stdout().into_raw_mode()?);
+    write!(screen, "{}", cursor::Hide)?;
+    let (full_width, full_height) = (scene.width, scene.height);
+    let mut size = (0, 0);
+
+    'preview: loop {
+        let terminal = termion::terminal_size()?;
+        if terminal != size {
+            size = terminal;
+            let (width, height) = fit_to_terminal(full_width, full_height, size.0, size.1);
+            scene.width = width;
+            scene.height = height;
+
+            write!(screen, "{}", clear::All)?;
+            draw_status(&mut screen, size, " rendering...")?;
+            screen.flush()?;
+            let image = scene.render(&mut Progress::hidden());
+            draw_image(&mut screen, &image, size.0)?;
+            draw_status(
+                &mut screen,
+                size,
+                &format!(
+                    " {}x{} preview of {}x{}, q to quit ",
+                    width, height, full_width, full_height
+                ),
+            )?;
+            screen.flush()?;
+        }
+
+        for key in keys.by_ref() {
+            match key? {
+                Key::Char('q') | Key::Esc | Key::Ctrl('c') => break 'preview,
+                _ => {}
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    write!(screen, "{}{}", style::Reset, cursor::Show)?;
+    screen.flush()
+}
@@ -0,0 +1,382 @@
+//! Progressive rendering: every pass adds one jittered sample per pixel to a float
+//! accumulation buffer until a sample, time or noise budget runs out.
+//! The current estimate can be flushed at any moment to keep an eye on long renders.
+use crate::{
+    checkpoint::Checkpoint,
+    entities::{Color, ViewBlock},
+    passes::{Pass, PassBuffers, PassSample},
+    rendering::{Ray, BLACK},
+    sampling::Rng,
+    scene::Scene,
+    stats::Progress,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use std::time::{Duration, Instant};
+
+/// Used when no budget is given at all
+const DEFAULT_SAMPLES: u32 = 16;
+/// Some pixels never settle (think aliased edges), stop there with only a noise threshold set
+const MAX_ADAPTIVE_SAMPLES: u32 = 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct Progressive {
+    /// Stop after this many samples per pixel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub samples: Option<u32>,
+    /// Stop after this many seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_limit: Option<f64>,
+    /// Stop sampling a pixel once the relative error of its mean drops below this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub noise_threshold: Option<f32>,
+    /// Samples every pixel gets before the noise threshold is checked
+    pub min_samples: u32,
+    /// Seconds between writes of the current estimate
+    pub flush_interval: f64,
+    pub seed: u64,
+}
+
+impl Default for Progressive {
+    fn default() -> Progressive {
+        Progressive {
+            samples: None,
+            time_limit: None,
+            noise_threshold: None,
+            min_samples: 4,
+            flush_interval: 10.0,
+            seed: 0,
+        }
+    }
+}
+
+impl Progressive {
+    fn max_samples(&self) -> Option<u32> {
+        match (self.samples, self.time_limit, self.noise_threshold) {
+            (None, None, None) => Some(DEFAULT_SAMPLES),
+            (None, None, Some(_)) => Some(MAX_ADAPTIVE_SAMPLES),
+            (samples, _, _) => samples,
+        }
+    }
+}
+
+fn luminance(c: Color) -> f32 {
+    0.2126 * c.red + 0.7152 * c.green + 0.0722 * c.blue
+}
+
+/// Running sums for every pixel.
+/// Guide passes (depth, normals, ...) come from the first, centered sample,
+/// the lighting passes are averaged over all of them.
+pub struct Accumulator {
+    pub width: u32,
+    pub height: u32,
+    pub guides: PassBuffers,
+    pub direct: Vec<Color>,
+    pub indirect: Vec<Color>,
+    /// Sum of squared luminance, for the noise estimate
+    pub luminance2: Vec<f32>,
+    pub counts: Vec<u32>,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Accumulator {
+        let size = (width * height) as usize;
+        Accumulator {
+            width,
+            height,
+            guides: PassBuffers::new(width, height),
+            direct: vec![BLACK; size],
+            indirect: vec![BLACK; size],
+            luminance2: vec![0.0; size],
+            counts: vec![0; size],
+        }
+    }
+
+    fn add(&mut self, x: u32, y: u32, sample: PassSample) {
+        let i = (y * self.width + x) as usize;
+        if self.counts[i] == 0 {
+            self.guides.set(x, y, sample);
+        }
+        self.direct[i] = self.direct[i] + sample.direct;
+        self.indirect[i] = self.indirect[i] + sample.indirect;
+        self.luminance2[i] += luminance(sample.beauty()).powi(2);
+        self.counts[i] += 1;
+    }
+
+    /// Relative standard error of the pixel mean
+    pub fn noise(&self, i: usize) -> f32 {
+        let n = self.counts[i] as f32;
+        if n < 2.0 {
+            return f32::INFINITY;
+        }
+        let mean = luminance(self.direct[i] + self.indirect[i]) / n;
+        let variance = (self.luminance2[i] / n - mean * mean).max(0.0) * n / (n - 1.0);
+        (variance / n).sqrt() / mean.max(0.01)
+    }
+
+    pub fn min_samples(&self) -> u32 {
+        self.counts.iter().copied().min().unwrap_or(0)
+    }
+
+    pub fn average_samples(&self) -> f32 {
+        self.counts.iter().map(|&c| c as f32).sum::<f32>() / self.counts.len().max(1) as f32
+    }
+
+    /// Current estimate of every pass
+    pub fn resolve(&self) -> PassBuffers {
+        let mut buffers = PassBuffers::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = (y * self.width + x) as usize;
+                let n = self.counts[i].max(1) as f32;
+                buffers.set(
+                    x,
+                    y,
+                    PassSample {
+                        direct: self.direct[i] * (1.0 / n),
+                        indirect: self.indirect[i] * (1.0 / n),
+                        ..*self.guides.get(x, y)
+                    },
+                );
+            }
+        }
+        buffers
+    }
+}
+
+pub struct ProgressiveRenderer<'a> {
+    scene: &'a Scene,
+    settings: Progressive,
+    /// Passes that will be saved, the others that take extra rays are skipped
+    passes: Vec<Pass>,
+    pub accumulator: Accumulator,
+    pub rng: Rng,
+    /// Full passes over the image done so far
+    pub pass: u32,
+}
+
+impl<'a> ProgressiveRenderer<'a> {
+    pub fn new(
+        scene: &'a Scene,
+        settings: Progressive,
+        passes: &[Pass],
+    ) -> ProgressiveRenderer<'a> {
+        ProgressiveRenderer {
+            scene,
+            settings,
+            passes: passes.to_vec(),
+            accumulator: Accumulator::new(scene.width, scene.height),
+            rng: Rng::new(settings.seed),
+            pass: 0,
+        }
+    }
+
+    /// Picks up where a checkpointed render stopped
+    pub fn resume(
+        scene: &'a Scene,
+        settings: Progressive,
+        passes: &[Pass],
+        checkpoint: Checkpoint,
+    ) -> ProgressiveRenderer<'a> {
+        ProgressiveRenderer {
+            scene,
+            settings,
+            passes: passes.to_vec(),
+            accumulator: checkpoint.accumulator,
+            rng: checkpoint.rng,
+            pass: checkpoint.pass,
+        }
+    }
+
+    fn converged(&self, i: usize) -> bool {
+        match self.settings.noise_threshold {
+            Some(threshold) => {
+                self.accumulator.counts[i] >= self.settings.min_samples.max(2)
+                    && self.accumulator.noise(i) < threshold
+            }
+            None => false,
+        }
+    }
+
+    /// Progress line sized for this render's budget
+    pub fn progress(&self) -> Progress {
+        let tiles = ViewBlock::tiles(self.scene.width, self.scene.height).len() as u64;
+        let mut progress = Progress::new(tiles * self.settings.max_samples().unwrap_or(1) as u64);
+        progress.time_limit = self.settings.time_limit.map(Duration::from_secs_f64);
+        progress
+    }
+
+    /// Keeps adding samples until a budget runs out.
+    /// `flush` gets the current estimate every `flush_interval` seconds.
+    pub fn run<F: FnMut(&ProgressiveRenderer)>(&mut self, progress: &mut Progress, mut flush: F) {
+        let start = Instant::now();
+        let time_limit = self.settings.time_limit.map(Duration::from_secs_f64);
+        let flush_interval = Duration::from_secs_f64(self.settings.flush_interval.max(0.0));
+        let mut last_flush = Instant::now();
+        let width = self.scene.width;
+        let tiles = ViewBlock::tiles(width, self.scene.height);
+        let spectral = self.scene.spectral.map(|settings| settings.sampler());
+        // a resumed render already has some of the work done
+        progress.tiles_done = tiles.len() as u64 * self.accumulator.min_samples() as u64;
+
+        'passes: loop {
+            if let Some(max) = self.settings.max_samples() {
+                if self.accumulator.min_samples() >= max {
+                    break;
+                }
+            }
+            let mut sampled = false;
+            for tile in &tiles {
+                if let Some(limit) = time_limit {
+                    // the first pass always completes, so every pixel has an estimate
+                    if self.pass > 0 && start.elapsed() >= limit {
+                        break 'passes;
+                    }
+                }
+                for (x, y) in tile.pixels() {
+                    let i = (y * width + x) as usize;
+                    if self.converged(i) {
+                        continue;
+                    }
+                    if let Some(max) = self.settings.max_samples() {
+                        if self.accumulator.counts[i] >= max {
+                            continue;
+                        }
+                    }
+                    // first sample goes through the pixel center, like a regular render
+                    let (offset, shutter) = if self.accumulator.counts[i] == 0 {
+                        ((0.5, 0.5), 0.5)
+                    } else {
+                        let offset = (self.rng.next_f64(), self.rng.next_f64());
+                        // without an exposure the jitter sequence stays what it always was
+                        let shutter = if self.scene.camera.has_exposure() {
+                            self.rng.next_f64()
+                        } else {
+                            0.5
+                        };
+                        (offset, shutter)
+                    };
+                    let mut ray = Ray::create_prime_offset(x, y, offset, shutter, self.scene);
+                    let weights = spectral.as_ref().map(|sampler| {
+                        let wavelength = sampler.wavelength(i as u32, self.accumulator.counts[i]);
+                        ray.wavelength = Some(wavelength);
+                        sampler.weights(wavelength)
+                    });
+                    let mut sample = match self.scene.path_tracing {
+                        Some(settings) => {
+                            self.scene
+                                .sample_path(&ray, settings, &self.passes, &mut self.rng)
+                        }
+                        None => self.scene.sample_passes(&ray, &self.passes),
+                    };
+                    if let Some(weights) = weights {
+                        sample.direct = sample.direct * weights;
+                        sample.indirect = sample.indirect * weights;
+                    }
+                    self.accumulator.add(x, y, sample);
+                    sampled = true;
+                }
+                progress.tile_done(&self.scene.stats);
+            }
+            self.pass += 1;
+            if !sampled {
+                // every pixel converged
+                break;
+            }
+            if last_flush.elapsed() >= flush_interval {
+                progress.finish();
+                flush(self);
+                last_flush = Instant::now();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::lit_sphere, entities::Material, rendering::WHITE};
+
+    fn lit(direct: f32, depth: f32) -> PassSample {
+        PassSample {
+            depth,
+            direct: Color::new(direct, direct, direct),
+            ..PassSample::default()
+        }
+    }
+
+    #[test]
+    fn running_mean() {
+        let mut acc = Accumulator::new(2, 1);
+        for (i, v) in [0.2, 0.4, 0.9].iter().enumerate() {
+            acc.add(0, 0, lit(*v, i as f32 + 1.0));
+        }
+        acc.add(1, 0, lit(1.0, 5.0));
+        let resolved = acc.resolve();
+        assert!((resolved.get(0, 0).direct.red - 0.5).abs() < 1e-6);
+        assert_eq!(resolved.get(1, 0).direct.red, 1.0);
+        // guides come from the first sample only
+        assert_eq!(resolved.get(0, 0).depth, 1.0);
+        assert_eq!(acc.counts, vec![3, 1]);
+        assert_eq!(acc.min_samples(), 1);
+        assert_eq!(acc.average_samples(), 2.0);
+    }
+
+    #[test]
+    fn noise_estimate() {
+        let mut acc = Accumulator::new(2, 1);
+        acc.add(0, 0, lit(0.5, 1.0));
+        assert_eq!(acc.noise(0), f32::INFINITY);
+        acc.add(0, 0, lit(0.5, 1.0));
+        assert_eq!(acc.noise(0), 0.0);
+        acc.add(1, 0, lit(0.2, 1.0));
+        acc.add(1, 0, lit(0.8, 1.0));
+        assert!(acc.noise(1) > 0.1);
+    }
+
+    fn render(scene: &Scene, settings: Progressive) -> ProgressiveRenderer<'_> {
+        let mut renderer = ProgressiveRenderer::new(scene, settings, &[]);
+        renderer.run(&mut Progress::hidden(), |_| {});
+        renderer
+    }
+
+    #[test]
+    fn sample_budget() {
+        let scene = lit_sphere(Material::diffuse(WHITE)).size(4, 4).build();
+        let settings = Progressive {
+            samples: Some(5),
+            ..Progressive::default()
+        };
+        let renderer = render(&scene, settings);
+        assert!(renderer.accumulator.counts.iter().all(|&c| c == 5));
+        assert_eq!(renderer.pass, 5);
+    }
+
+    #[test]
+    fn noise_budget_stops_at_min_samples() {
+        // nothing to hit, every sample of every pixel is the same
+        let scene = Scene::builder().size(4, 4).build();
+        let settings = Progressive {
+            noise_threshold: Some(0.01),
+            min_samples: 6,
+            ..Progressive::default()
+        };
+        let renderer = render(&scene, settings);
+        assert_eq!(renderer.accumulator.min_samples(), 6);
+        assert_eq!(renderer.accumulator.average_samples(), 6.0);
+    }
+
+    #[test]
+    fn same_seed_same_render() {
+        let scene = lit_sphere(Material::diffuse(WHITE)).size(4, 4).build();
+        let settings = Progressive {
+            samples: Some(3),
+            seed: 9,
+            ..Progressive::default()
+        };
+        let (a, b) = (render(&scene, settings), render(&scene, settings));
+        assert_eq!(a.accumulator.direct, b.accumulator.direct);
+        assert_eq!(a.rng.state(), b.rng.state());
+    }
+}
@@ -0,0 +1,175 @@
+//! Normal and bump maps. They don't move the surface, only the normals shading sees, which
+//! is enough to make flat geometry look rough or carved.
+//!
+//! Both are read along the element's tangent frame, the directions its texture coordinates
+//! grow in, so they line up with the element's color texture.
+use crate::{
+    entities::{Intersection, Texture},
+    point::Point,
+    rendering::{Intersectable, TextureCoords},
+    vector::Vector3,
+};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Relief {
+    /// Tangent space normals, red along the texture's x, green up the image and blue out of
+    /// the surface, the usual purple-blue normal map
+    NormalMap {
+        texture: Texture,
+        /// Scales how far the normals lean, 0 leaves the surface flat
+        #[serde(default = "Relief::default_strength")]
+        strength: f32,
+    },
+    /// Grayscale heights, white is high
+    BumpMap {
+        texture: Texture,
+        /// How steep a step from black to white between neighbouring texels looks
+        #[serde(default = "Relief::default_strength")]
+        strength: f32,
+    },
+}
+
+impl Relief {
+    fn default_strength() -> f32 {
+        1.0
+    }
+
+    /// `normal` bent by the map at `coords`, `tangent` and `bitangent` are the directions
+    /// the coordinates' x and y grow in
+    pub fn perturb(
+        &self,
+        normal: Vector3,
+        (tangent, bitangent): (Vector3, Vector3),
+        coords: &TextureCoords,
+    ) -> Vector3 {
+        let bent = match self {
+            Relief::NormalMap { texture, strength } => {
+                let rgb = texture.pixel(coords).0;
+                // 128 is level, the way normal map tools write a flat surface
+                let channel = |i: usize| ((rgb[i] as f64 - 128.0) / 127.0).max(-1.0);
+                let strength = *strength as f64;
+                // image rows grow downwards, against green
+                tangent * (channel(0) * strength) - bitangent * (channel(1) * strength)
+                    + normal * channel(2).max(0.0)
+            }
+            Relief::BumpMap { texture, strength } => {
+                let (width, height) = (texture.image.width(), texture.image.height());
+                let height_at = |dx: f32, dy: f32| {
+                    let rgb = texture
+                        .pixel(&TextureCoords {
+                            x: coords.x + dx / width as f32,
+                            y: coords.y + dy / height as f32,
+                        })
+                        .0;
+                    (rgb[0] as f64 + rgb[1] as f64 + rgb[2] as f64) / (3.0 * 255.0)
+                };
+                let slope_x = (height_at(1.0, 0.0) - height_at(-1.0, 0.0)) / 2.0;
+                let slope_y = (height_at(0.0, 1.0) - height_at(0.0, -1.0)) / 2.0;
+                // lean away from where the surface rises
+                normal - (tangent * slope_x + bitangent * slope_y) * *strength as f64
+            }
+        };
+        if bent.length() == 0.0 {
+            normal
+        } else {
+            bent.normalize()
+        }
+    }
+}
+
+impl<'a> Intersection<'a> {
+    /// The normal to shade a hit point with, the element's own one bent by its material's
+    /// relief
+    pub fn shading_normal(&self, hit_point: Point) -> Vector3 {
+        let local = self.local(hit_point);
+        let normal = self.element.surface_normal(&local);
+        match &self.element.material().relief {
+            Some(relief) => relief.perturb(
+                normal,
+                self.element.tangent_frame(&local),
+                &self.element.texture_coords(&local),
+            ),
+            None => normal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Rgba, RgbaImage};
+
+    fn texture(texels: &[[u8; 3]]) -> Texture {
+        let mut image = RgbaImage::new(texels.len() as u32, 1);
+        for (x, [r, g, b]) in texels.iter().enumerate() {
+            image.put_pixel(x as u32, 0, Rgba([*r, *g, *b, 255]));
+        }
+        Texture {
+            path: "relief.png".into(),
+            image: DynamicImage::ImageRgba8(image),
+        }
+    }
+
+    const FRAME: (Vector3, Vector3) = (
+        Vector3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        Vector3 {
+            x: 0.0,
+            y: -1.0,
+            z: 0.0,
+        },
+    );
+    const NORMAL: Vector3 = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+    };
+
+    fn perturb(relief: &Relief, x: f32) -> Vector3 {
+        relief.perturb(NORMAL, FRAME, &TextureCoords { x, y: 0.5 })
+    }
+
+    #[test]
+    fn flat_normal_map_keeps_the_normal() {
+        let relief = Relief::NormalMap {
+            texture: texture(&[[128, 128, 255]]),
+            strength: 1.0,
+        };
+        assert!((perturb(&relief, 0.5) - NORMAL).length() < 1e-12);
+    }
+
+    #[test]
+    fn normal_map_leans_along_the_tangent() {
+        let relief = Relief::NormalMap {
+            texture: texture(&[[255, 128, 128]]),
+            strength: 1.0,
+        };
+        let bent = perturb(&relief, 0.5);
+        assert!(bent.x > 0.9 && bent.y.abs() < 1e-12);
+    }
+
+    #[test]
+    fn constant_bump_map_keeps_the_normal() {
+        let relief = Relief::BumpMap {
+            texture: texture(&[[90, 90, 90]; 4]),
+            strength: 5.0,
+        };
+        for x in [0.0, 0.3, 0.9] {
+            assert!((perturb(&relief, x) - NORMAL).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn bumps_lean_away_from_the_rise() {
+        // rises towards +x
+        let relief = Relief::BumpMap {
+            texture: texture(&[[0, 0, 0], [100, 100, 100], [200, 200, 200], [250, 250, 250]]),
+            strength: 1.0,
+        };
+        assert!(perturb(&relief, 0.375).x < 0.0);
+    }
+}
@@ -12,9 +12,20 @@ pub const BLACK: Color = Color {
     blue: 0.0,
 };
 
+pub const WHITE: Color = Color {
+    red: 1.0,
+    green: 1.0,
+    blue: 1.0,
+};
+
+#[derive(Clone, Copy, Debug)]
 pub struct Ray {
     pub origin: Point,
     pub direction: Vector3,
+    /// Seconds into the animation, moving elements are intersected where they are at this time
+    pub time: f64,
+    /// Nanometers of the light a spectral sample follows, `None` carries red, green and blue
+    pub wavelength: Option<f64>,
 }
 
 impl Default for Ray {
@@ -22,26 +33,38 @@ impl Default for Ray {
         Ray {
             origin: Point::zero(),
             direction: Vector3::zero(),
+            time: 0.0,
+            wavelength: None,
         }
     }
 }
 
 impl Ray {
     pub fn create_prime(x: u32, y: u32, scene: &Scene) -> Ray {
+        Ray::create_prime_offset(x, y, (0.5, 0.5), 0.5, scene)
+    }
+
+    /// Prime ray through an arbitrary point of the pixel, `offset` is in [0, 1) on both axes.
+    /// `shutter` in [0, 1] picks the moment between the camera shutter opening and closing.
+    pub fn create_prime_offset(
+        x: u32,
+        y: u32,
+        offset: (f64, f64),
+        shutter: f64,
+        scene: &Scene,
+    ) -> Ray {
+        scene.stats.primary_ray();
         let fov_adjustment = (scene.fov.to_radians() / 2.0).tan();
         // take non-quadratic images into account
         let aspect_ratio = (scene.width as f64) / (scene.height as f64);
-        let sensor_x =
-            ((((x as f64 + 0.5) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio) * fov_adjustment;
-        let sensor_y = (1.0 - ((y as f64 + 0.5) / scene.height as f64) * 2.0) * fov_adjustment;
+        let sensor_x = ((((x as f64 + offset.0) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio)
+            * fov_adjustment;
+        let sensor_y = (1.0 - ((y as f64 + offset.1) / scene.height as f64) * 2.0) * fov_adjustment;
         Ray {
-            origin: Point::zero(),
-            direction: Vector3 {
-                x: sensor_x,
-                y: sensor_y,
-                z: -1.0,
-            }
-            .normalize(),
+            origin: scene.camera.position,
+            direction: scene.camera.direction_through(sensor_x, sensor_y),
+            time: scene.time + scene.camera.shutter_time(shutter),
+            wavelength: None,
         }
     }
 
@@ -78,6 +101,7 @@ impl Ray {
         Ray {
             origin: intersection + (normal * bias),
             direction: incident - 2.0 * (incident.dot(&normal) * normal),
+            ..Ray::default()
         }
     }
 
@@ -153,6 +177,7 @@ impl Ray {
             Some(Ray {
                 origin: intersection + (ref_n * -bias),
                 direction: (incident + i_dot_n * ref_n) * eta - ref_n * k.sqrt(),
+                ..Ray::default()
             })
         }
     }
@@ -168,13 +193,24 @@ pub trait Intersectable {
 
     fn surface_normal(&self, hit_point: &Point) -> Vector3;
     fn texture_coords(&self, hit_point: &Point) -> TextureCoords;
+    /// Unit directions the texture's x and y coordinates grow in at the hit point
+    fn tangent_frame(&self, hit_point: &Point) -> (Vector3, Vector3);
 }
 
 impl Intersectable for Element {
+    /// Moving elements are tested where they are at the ray's time, by moving the ray
+    /// the other way
     fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let ray = match self.motion() {
+            Some(motion) => Ray {
+                origin: ray.origin - motion.offset_at(ray.time),
+                ..*ray
+            },
+            None => *ray,
+        };
         match *self {
-            Element::Sphere(ref s) => s.intersect(ray),
-            Element::Plane(ref p) => p.intersect(ray),
+            Element::Sphere(ref s) => s.intersect(&ray),
+            Element::Plane(ref p) => p.intersect(&ray),
         }
     }
     fn surface_normal(&self, hit_point: &Point) -> Vector3 {
@@ -189,6 +225,31 @@ impl Intersectable for Element {
             Element::Plane(ref p) => p.texture_coords(hit_point),
         }
     }
+    fn tangent_frame(&self, hit_point: &Point) -> (Vector3, Vector3) {
+        match *self {
+            Element::Sphere(ref s) => s.tangent_frame(hit_point),
+            Element::Plane(ref p) => p.tangent_frame(hit_point),
+        }
+    }
+}
+
+impl Plane {
+    fn texture_axes(&self) -> (Vector3, Vector3) {
+        let mut x_axis = self.normal.cross(&Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        });
+        if x_axis.length() == 0.0 {
+            x_axis = self.normal.cross(&Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            });
+        }
+        let y_axis = self.normal.cross(&x_axis);
+        (x_axis, y_axis)
+    }
 }
 
 impl Intersectable for Plane {
@@ -208,25 +269,17 @@ impl Intersectable for Plane {
         -self.normal.normalize()
     }
     fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
-        let mut x_axis = self.normal.cross(&Vector3 {
-            x: 0.0,
-            y: 0.0,
-            z: 1.0,
-        });
-        if x_axis.length() == 0.0 {
-            x_axis = self.normal.cross(&Vector3 {
-                x: 0.0,
-                y: 1.0,
-                z: 0.0,
-            });
-        }
-        let y_axis = self.normal.cross(&x_axis);
+        let (x_axis, y_axis) = self.texture_axes();
         let hit_vec = *hit_point - self.origin;
         TextureCoords {
             x: hit_vec.dot(&x_axis) as f32,
             y: hit_vec.dot(&y_axis) as f32,
         }
     }
+    fn tangent_frame(&self, _: &Point) -> (Vector3, Vector3) {
+        let (x_axis, y_axis) = self.texture_axes();
+        (x_axis.normalize(), y_axis.normalize())
+    }
 }
 
 impl Intersectable for Sphere {
@@ -263,4 +316,93 @@ impl Intersectable for Sphere {
             y: (hit_vec.y / self.radius).acos() as f32 / PI,
         }
     }
+    fn tangent_frame(&self, hit_point: &Point) -> (Vector3, Vector3) {
+        let normal = self.surface_normal(hit_point);
+        // around the y axis, any direction at the poles
+        let mut tangent = Vector3::new(-normal.z, 0.0, normal.x);
+        if tangent.length() == 0.0 {
+            tangent = Vector3::new(1.0, 0.0, 0.0);
+        }
+        let tangent = tangent.normalize();
+        // down from the north pole
+        (tangent, normal.cross(&tangent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::Material;
+
+    /// The tangent has to stand on the normal and point where `texture_coords.x` grows
+    fn check_tangent<E: Intersectable>(
+        element: &E,
+        hit_point: Point,
+        project: impl Fn(Point) -> Point,
+    ) {
+        let normal = element.surface_normal(&hit_point);
+        let (tangent, bitangent) = element.tangent_frame(&hit_point);
+        assert!(
+            tangent.dot(&normal).abs() < 1e-9,
+            "{:?} at {:?}",
+            tangent,
+            hit_point
+        );
+        assert!(bitangent.dot(&normal).abs() < 1e-9);
+        assert!((tangent.length() - 1.0).abs() < 1e-9);
+        let step = project(hit_point + tangent * 1e-3);
+        let (here, there) = (
+            element.texture_coords(&hit_point).x,
+            element.texture_coords(&step).x,
+        );
+        assert!(
+            there > here,
+            "x goes from {} to {} at {:?}",
+            here,
+            there,
+            hit_point
+        );
+    }
+
+    #[test]
+    fn sphere_tangents() {
+        let sphere = Sphere {
+            center: Point::new(1.0, 2.0, 3.0),
+            radius: 2.0,
+            material: Material::diffuse(WHITE),
+            motion: None,
+        };
+        let onto_sphere =
+            |p: Point| sphere.center + (p - sphere.center).normalize() * sphere.radius;
+        for direction in [
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(-1.0, -0.5, 0.3),
+        ] {
+            check_tangent(&sphere, onto_sphere(sphere.center + direction), onto_sphere);
+        }
+        // at the poles any tangent will do, as long as it lies on the surface
+        let pole = sphere.center + Vector3::new(0.0, 2.0, 0.0);
+        let (tangent, _) = sphere.tangent_frame(&pole);
+        assert!(tangent.dot(&sphere.surface_normal(&pole)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn plane_tangents() {
+        for normal in [
+            Vector3::new(0.0, -1.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(1.0, 2.0, -0.5).normalize(),
+        ] {
+            let plane = Plane {
+                origin: Point::new(0.0, -2.0, 0.0),
+                normal,
+                material: Material::diffuse(WHITE),
+                motion: None,
+            };
+            check_tangent(&plane, Point::new(0.0, -2.0, 0.0), |p| p);
+        }
+    }
 }
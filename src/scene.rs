@@ -1,23 +1,103 @@
 use crate::{
-    entities::{Color, Element, Intersection, Light, SurfaceType, DEPTH},
+    camera::Camera,
+    denoise::Denoiser,
+    entities::{Color, Element, Intersection, Light, SurfaceType, ViewBlock, DEPTH},
+    grid::VolumeGrid,
+    passes::{Pass, PassBuffers, PassSample},
+    path_tracing::PathTracing,
     point::Point,
+    progressive::Progressive,
     rendering::{Intersectable, Ray, BLACK},
+    sky::Sky,
+    spectral::Spectral,
+    stats::{Progress, RenderStats},
     vector::Vector3,
+    volume::{Fog, Volumes},
 };
 use image::{DynamicImage, GenericImage, Pixel, Rgba};
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 
 use std::f32::consts::PI;
 
-#[derive(Deserialize, Debug)]
+/// Switches for the expensive parts of the lighting, handy while exploring a scene
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct Effects {
+    pub shadows: bool,
+    pub reflections: bool,
+    pub refractions: bool,
+}
+
+impl Default for Effects {
+    fn default() -> Effects {
+        Effects {
+            shadows: true,
+            reflections: true,
+            refractions: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Scene {
     pub width: u32,
     pub height: u32,
     pub fov: f64,
+    #[serde(default)]
+    pub camera: Camera,
+    /// Seconds into the animation, where moving elements are when the shutter opens.
+    /// Animated scenes set it for every frame.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub time: f64,
+    #[serde(default)]
+    pub effects: Effects,
     pub elements: Vec<Element>,
     pub lights: Vec<Light>,
     pub shadow_bias: f64,
     pub max_recursion_depth: u32,
+    /// Render passes saved next to the beauty image
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub passes: Vec<Pass>,
+    /// Filter the image after rendering
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denoise: Option<Denoiser>,
+    /// Accumulate jittered samples until a budget runs out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progressive: Option<Progressive>,
+    /// Follow random paths to light diffuse surfaces with each other and with emissive
+    /// elements. Always renders progressively.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_tracing: Option<PathTracing>,
+    /// Daylight for rays that miss everything, with a sun light to match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sky: Option<Sky>,
+    /// Fades everything with distance, and can show shafts of light
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fog: Option<Fog>,
+    /// How finely light is gathered in fog and the media of elements
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volumes: Option<Volumes>,
+    /// Smoke and clouds with densities read from voxel grid files
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volume_grids: Vec<VolumeGrid>,
+    /// Trace one wavelength per sample, so dispersive glass splits light into colors.
+    /// Always renders progressively.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spectral: Option<Spectral>,
+    #[serde(skip)]
+    pub stats: RenderStats,
+}
+
+/// Color at a hit point split by the way the light got there
+struct Shading {
+    direct: Color,
+    indirect: Color,
+}
+
+impl Shading {
+    fn total(&self) -> Color {
+        self.direct + self.indirect
+    }
 }
 
 impl Scene {
@@ -25,112 +105,307 @@ impl Scene {
         if depth >= self.max_recursion_depth {
             return BLACK;
         }
-        let intersection = self.trace(&ray);
-        intersection
+        self.stats.depth_reached(depth);
+        let intersection = self.trace(ray);
+        let distance = intersection.as_ref().map_or(f64::INFINITY, |i| i.distance);
+        let color = intersection
             .map(|i| self.get_color(ray, &i, depth))
-            .unwrap_or(BLACK)
+            .unwrap_or_else(|| self.background(ray));
+        match self.media_along(ray, distance) {
+            Some(segment) => segment.apply(color),
+            None => color,
+        }
+    }
+
+    /// What rays that miss everything see
+    pub fn background(&self, ray: &Ray) -> Color {
+        self.sky
+            .as_ref()
+            .map_or(BLACK, |sky| sky.radiance(ray.direction))
+    }
+
+    /// The sun of the sky, lighting the scene together with `lights`
+    pub fn sun(&self) -> Option<Light> {
+        self.sky.as_ref().map(Sky::sun)
     }
 
-    pub fn render(&self) -> DynamicImage {
+    pub fn render(&self, progress: &mut Progress) -> DynamicImage {
         let mut image = DynamicImage::new_rgb8(self.width, self.height);
         let black = Rgba::from_slice(&[0u8, 0u8, 0u8, 0u8]);
-        for x in 0..self.width {
-            for y in 0..self.height {
+        for tile in ViewBlock::tiles(self.width, self.height) {
+            for (x, y) in tile.pixels() {
                 let ray = Ray::create_prime(x, y, self);
                 let intersection = self.trace(&ray);
-                let color = intersection
-                    .map(|i| self.get_color(&ray, &i, DEPTH).to_rgba())
+                let distance = intersection.as_ref().map_or(f64::INFINITY, |i| i.distance);
+                let segment = self.media_along(&ray, distance);
+                let color = match intersection {
+                    Some(i) => Some(self.get_color(&ray, &i, DEPTH)),
+                    None if self.sky.is_some() || segment.is_some() => Some(self.background(&ray)),
+                    None => None,
+                };
+                let pixel = color
+                    .map(|c| segment.map_or(c, |s| s.apply(c)).to_rgba())
                     .unwrap_or(black.to_owned());
-                image.put_pixel(x, y, color);
+                image.put_pixel(x, y, pixel);
             }
+            progress.tile_done(&self.stats);
         }
         image
     }
 
-    fn trace(&self, ray: &Ray) -> Option<Intersection> {
+    /// Same as [`Scene::render`] but also fills the render passes. Passes that take extra
+    /// rays are only filled when they are in `passes`.
+    pub fn render_passes(&self, passes: &[Pass], progress: &mut Progress) -> PassBuffers {
+        let mut buffers = PassBuffers::new(self.width, self.height);
+        for tile in ViewBlock::tiles(self.width, self.height) {
+            for (x, y) in tile.pixels() {
+                buffers.set(
+                    x,
+                    y,
+                    self.sample_passes(&Ray::create_prime(x, y, self), passes),
+                );
+            }
+            progress.tile_done(&self.stats);
+        }
+        buffers
+    }
+
+    pub fn sample_passes(&self, ray: &Ray, passes: &[Pass]) -> PassSample {
+        let (sample, distance) = self.sample_surface(ray, passes);
+        match self.media_along(ray, distance) {
+            // light from the media counts as coming from somewhere else
+            Some(segment) => PassSample {
+                direct: sample.direct * segment.transmittance,
+                indirect: segment.apply(sample.indirect),
+                ..sample
+            },
+            None => sample,
+        }
+    }
+
+    /// Passes of the surface the ray hits and how far away it is
+    fn sample_surface(&self, ray: &Ray, passes: &[Pass]) -> (PassSample, f64) {
+        let intersection = match self.trace(ray) {
+            Some(i) => i,
+            None => {
+                let sample = PassSample {
+                    direct: self.background(ray),
+                    ..PassSample::default()
+                };
+                return (sample, f64::INFINITY);
+            }
+        };
+        let hit = ray.origin + (ray.direction * intersection.distance);
+        let normal = intersection.shading_normal(hit);
+        let texture_coords = intersection
+            .element
+            .texture_coords(&intersection.local(hit));
+        let shading = self.shade(ray, &intersection, DEPTH);
+
+        let sample = PassSample {
+            depth: (intersection.distance * ray.direction.dot(&self.camera.direction)) as f32,
+            normal,
+            albedo: intersection
+                .element
+                .material()
+                .coloration
+                .color(&texture_coords),
+            direct: shading.direct,
+            indirect: shading.indirect,
+            shadow: self.shadow_pass(passes, hit, normal, ray.time),
+            object_id: self.object_id(intersection.element),
+        };
+        (sample, intersection.distance)
+    }
+
+    /// 1-based index of the element, 0 is left for the background
+    pub fn object_id(&self, element: &Element) -> u32 {
+        self.elements
+            .iter()
+            .position(|e| std::ptr::eq(e, element))
+            .map_or(0, |i| i as u32 + 1)
+    }
+
+    /// The shadow pass at a hit, it takes a shadow ray per light so it is left at 0.0
+    /// unless `passes` asks for it
+    pub fn shadow_pass(
+        &self,
+        passes: &[Pass],
+        hit_point: Point,
+        normal: Vector3,
+        time: f64,
+    ) -> f32 {
+        if passes.contains(&Pass::Shadow) {
+            self.shadow_fraction(hit_point, normal, time)
+        } else {
+            0.0
+        }
+    }
+
+    /// Share of the light that can't reach the point, 0.0 when fully lit
+    pub fn shadow_fraction(&self, hit_point: Point, surface_normal: Vector3, time: f64) -> f32 {
+        let sun = self.sun();
+        let lights = self.lights.len() + sun.iter().count();
+        if lights == 0 {
+            return 0.0;
+        }
+        let occluded: f32 = self
+            .lights
+            .iter()
+            .chain(&sun)
+            .map(|light| {
+                let t = self.light_transmittance(light, hit_point, surface_normal, time);
+                1.0 - (t.red + t.green + t.blue) / 3.0
+            })
+            .sum();
+        occluded / lights as f32
+    }
+
+    pub fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.stats.intersection_tests(self.elements.len());
         self.elements
             .iter()
-            .filter_map(|e| e.intersect(ray).map(|d| Intersection::new(d, e)))
+            .filter_map(|e| {
+                e.intersect_opaque(ray)
+                    .map(|d| Intersection::new(d, e, e.offset_at(ray.time)))
+            })
             .min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
     }
 
-    fn shade_diffuse(&self, element: &Element, hit_point: Point, surface_normal: Vector3) -> Color {
-        let texture_coords = element.texture_coords(&hit_point);
+    pub fn shade_diffuse(
+        &self,
+        intersection: &Intersection,
+        hit_point: Point,
+        surface_normal: Vector3,
+        time: f64,
+    ) -> Color {
+        let element = intersection.element;
+        let texture_coords = element.texture_coords(&intersection.local(hit_point));
         let mut color = BLACK;
-        for light in &self.lights {
+        let sun = self.sun();
+        for light in self.lights.iter().chain(&sun) {
             let direction_to_light = light.direction_from(&hit_point);
+            let transmittance = self.light_transmittance(light, hit_point, surface_normal, time);
+            if transmittance == BLACK {
+                continue;
+            }
 
-            let shadow_ray = Ray {
-                origin: hit_point + (surface_normal * self.shadow_bias),
-                direction: direction_to_light,
-            };
-            let shadow_intersection = self.trace(&shadow_ray);
-            let in_light = shadow_intersection.is_none()
-                || shadow_intersection.unwrap().distance > light.distance(&hit_point);
-
-            let light_intensity = if in_light {
-                light.intensity(&hit_point)
-            } else {
-                0.0
-            };
             let material = element.material();
-            let light_power =
-                (surface_normal.dot(&direction_to_light) as f32).max(0.0) * light_intensity;
+            let light_power = (surface_normal.dot(&direction_to_light) as f32).max(0.0)
+                * light.intensity(&hit_point);
             let light_reflected = material.albedo / PI;
 
-            let light_color = light.color() * light_power * light_reflected;
+            let light_color =
+                light.color_at(&hit_point) * light_power * light_reflected * transmittance;
             color = color + (material.coloration.color(&texture_coords) * light_color);
         }
         color.clamp()
     }
 
     fn get_color(&self, ray: &Ray, intersection: &Intersection, depth: u32) -> Color {
+        self.shade(ray, intersection, depth).total()
+    }
+
+    fn shade(&self, ray: &Ray, intersection: &Intersection, depth: u32) -> Shading {
         let hit = ray.origin + (ray.direction * intersection.distance);
-        let normal = intersection.element.surface_normal(&hit);
+        let normal = intersection.shading_normal(hit);
 
         let material = intersection.element.material();
-        match material.surface {
-            SurfaceType::Diffuse => self.shade_diffuse(intersection.element, hit, normal),
+        let diffuse = || Shading {
+            direct: self.shade_diffuse(intersection, hit, normal, ray.time),
+            indirect: BLACK,
+        };
+        let mut shading = match material.surface {
+            SurfaceType::Diffuse => diffuse(),
+            SurfaceType::Reflective { .. } if !self.effects.reflections => diffuse(),
+            SurfaceType::Refractive { .. } if !self.effects.refractions => diffuse(),
+            SurfaceType::Conductor(_) if !self.effects.reflections => diffuse(),
             SurfaceType::Reflective { reflectivity } => {
-                let mut color = self.shade_diffuse(intersection.element, hit, normal);
-                let reflection_ray =
-                    Ray::create_reflection(normal, ray.direction, hit, self.shadow_bias);
-                color = color * (1.0 - reflectivity);
-                color = color + (self.cast_ray(&reflection_ray, depth + 1) * reflectivity);
-                color
+                let color = self.shade_diffuse(intersection, hit, normal, ray.time);
+                let reflection_ray = Ray {
+                    time: ray.time,
+                    wavelength: ray.wavelength,
+                    ..Ray::create_reflection(normal, ray.direction, hit, self.shadow_bias)
+                };
+                self.stats.reflection_ray();
+                Shading {
+                    direct: color * (1.0 - reflectivity),
+                    indirect: self.cast_ray(&reflection_ray, depth + 1) * reflectivity,
+                }
+            }
+            SurfaceType::Conductor(metal) => {
+                let reflection_ray = Ray {
+                    time: ray.time,
+                    wavelength: ray.wavelength,
+                    ..Ray::create_reflection(normal, ray.direction, hit, self.shadow_bias)
+                };
+                self.stats.reflection_ray();
+                // metals don't scatter light diffusely, all of it is reflected or absorbed
+                Shading {
+                    direct: BLACK,
+                    indirect: self.cast_ray(&reflection_ray, depth + 1)
+                        * metal.fresnel(ray.direction, normal),
+                }
             }
             SurfaceType::Refractive {
                 index,
                 transparency,
             } => {
                 let mut refraction_color = BLACK;
+                let index = index.at(ray.wavelength);
                 let kr = self.fresnel(ray.direction, normal, index) as f32;
-                let surface_color = material
-                    .coloration
-                    .color(&intersection.element.texture_coords(&hit));
+                let surface_color = material.coloration.color(
+                    &intersection
+                        .element
+                        .texture_coords(&intersection.local(hit)),
+                );
 
                 if kr < 1.0 {
-                    let transmission_ray = Ray::create_transmission(
-                        normal,
-                        ray.direction,
-                        hit,
-                        self.shadow_bias,
-                        index,
-                    )
-                    .unwrap();
+                    let transmission_ray = Ray {
+                        time: ray.time,
+                        wavelength: ray.wavelength,
+                        ..Ray::create_transmission(
+                            normal,
+                            ray.direction,
+                            hit,
+                            self.shadow_bias,
+                            index,
+                        )
+                        .unwrap()
+                    };
+                    self.stats.refraction_ray();
                     refraction_color = self.cast_ray(&transmission_ray, depth + 1);
                 }
 
-                let reflection_ray =
-                    Ray::create_reflection(normal, ray.direction, hit, self.shadow_bias);
-                let reflection_color = self.cast_ray(&reflection_ray, depth + 1);
+                let mut reflection_color = BLACK;
+                if self.effects.reflections {
+                    let reflection_ray = Ray {
+                        time: ray.time,
+                        wavelength: ray.wavelength,
+                        ..Ray::create_reflection(normal, ray.direction, hit, self.shadow_bias)
+                    };
+                    self.stats.reflection_ray();
+                    reflection_color = self.cast_ray(&reflection_ray, depth + 1);
+                }
                 let mut color = reflection_color * kr + refraction_color * (1.0 - kr);
                 color = color * transparency * surface_color;
-                color
+                // everything a transparent surface shows came from somewhere else
+                Shading {
+                    direct: BLACK,
+                    indirect: color,
+                }
             }
+        };
+        if material.emission.is_some() {
+            let coords = intersection
+                .element
+                .texture_coords(&intersection.local(hit));
+            shading.direct = shading.direct + material.emitted(&coords);
         }
+        shading
     }
-    fn fresnel(&self, incident: Vector3, normal: Vector3, index: f32) -> f64 {
+
+    pub fn fresnel(&self, incident: Vector3, normal: Vector3, index: f32) -> f64 {
         let i_dot_n = incident.dot(&normal);
         let mut eta_i = 1.0;
         let mut eta_t = index as f64;
@@ -141,13 +416,86 @@ impl Scene {
         let sin_t = eta_i / eta_t * (1.0 - i_dot_n * i_dot_n).max(0.0).sqrt();
         if sin_t > 1.0 {
             //Total internal reflection
-            return 1.0;
+            1.0
         } else {
             let cos_t = (1.0 - sin_t * sin_t).max(0.0).sqrt();
             let cos_i = cos_t.abs();
             let r_s = ((eta_t * cos_i) - (eta_i * cos_t)) / ((eta_t * cos_i) + (eta_i * cos_t));
             let r_p = ((eta_i * cos_i) - (eta_t * cos_t)) / ((eta_i * cos_i) + (eta_t * cos_t));
-            return (r_s * r_s + r_p * r_p) / 2.0;
+            (r_s * r_s + r_p * r_p) / 2.0
+        }
+    }
+}
+
+fn is_zero(time: &f64) -> bool {
+    *time == 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::{Material, Motion, Sphere},
+        rendering::WHITE,
+    };
+
+    /// A glowing sphere that arrives at `center` at time 1, 100 units to the right of it
+    /// at time 0
+    fn arriving(center: Point) -> Element {
+        Element::Sphere(Sphere {
+            center: center + Vector3::new(100.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Material::emissive(WHITE, 1.0),
+            motion: Some(Motion::Velocity(Vector3::new(-100.0, 0.0, 0.0))),
+        })
+    }
+
+    fn forward(time: f64) -> Ray {
+        Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            time,
+            wavelength: None,
         }
     }
+
+    #[test]
+    fn moving_elements_are_where_the_ray_time_puts_them() {
+        let scene = Scene::builder()
+            .element(arriving(Point::new(0.0, 0.0, -5.0)))
+            .build();
+        assert!(scene.trace(&forward(0.0)).is_none());
+        let hit = scene.trace(&forward(1.0)).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reflections_keep_the_ray_time() {
+        // the mirror in front of the camera shows what's behind it
+        let scene = Scene::builder()
+            .plane(
+                Point::new(0.0, 0.0, -10.0),
+                Vector3::new(0.0, 0.0, -1.0),
+                Material::reflective(WHITE, 1.0),
+            )
+            .element(arriving(Point::new(0.0, 0.0, 5.0)))
+            .build();
+        assert_eq!(scene.cast_ray(&forward(0.0), 0), BLACK);
+        assert!(scene.cast_ray(&forward(1.0), 0).red > 0.5);
+    }
+
+    #[test]
+    fn refractions_keep_the_ray_time() {
+        // glass that doesn't bend light, in front of where the sphere arrives
+        let scene = Scene::builder()
+            .sphere(
+                Point::new(0.0, 0.0, -5.0),
+                1.0,
+                Material::refractive(WHITE, 1.0, 1.0),
+            )
+            .element(arriving(Point::new(0.0, 0.0, -10.0)))
+            .build();
+        assert_eq!(scene.cast_ray(&forward(0.0), 0), BLACK);
+        assert!(scene.cast_ray(&forward(1.0), 0).red > 0.5);
+    }
 }
@@ -0,0 +1,303 @@
+//! Analytic daylight after Preetham, Shirley and Smits, "A Practical Analytic Model for
+//! Daylight" (1999). The sky's color for any direction follows from where the sun is and
+//! how hazy the air is, and the sun itself becomes a directional light reddened by the
+//! air its light passes through.
+use crate::{
+    entities::{Color, DirectionalLight, Light},
+    rendering::BLACK,
+    spectral::xyz_to_rgb,
+    vector::Vector3,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use std::f64::consts::PI;
+
+/// Brings the model's luminance in kcd/m² to where the renderer's colors live
+const LUMINANCE_SCALE: f64 = 1.0 / 15.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Sky {
+    /// Degrees above the horizon
+    pub sun_elevation: f64,
+    /// Degrees clockwise from -z when looking down, 90 puts the sun towards +x
+    pub sun_azimuth: f64,
+    /// Haziness of the air, 2 is a clear day, 10 is thick haze
+    #[serde(default = "Sky::default_turbidity")]
+    pub turbidity: f64,
+    /// How much light the ground below the horizon reflects
+    #[serde(default = "Sky::default_ground_albedo")]
+    pub ground_albedo: f32,
+    /// Scales the sky's brightness
+    #[serde(default = "Sky::default_intensity")]
+    pub intensity: f32,
+    /// Intensity of the sun light before the air reddens it
+    #[serde(default = "Sky::default_sun_intensity")]
+    pub sun_intensity: f32,
+}
+
+impl Sky {
+    fn default_turbidity() -> f64 {
+        3.0
+    }
+
+    fn default_ground_albedo() -> f32 {
+        0.3
+    }
+
+    fn default_intensity() -> f32 {
+        1.0
+    }
+
+    fn default_sun_intensity() -> f32 {
+        5.0
+    }
+
+    /// Unit vector pointing at the sun
+    pub fn sun_direction(&self) -> Vector3 {
+        let (elevation, azimuth) = (
+            self.sun_elevation.to_radians(),
+            self.sun_azimuth.to_radians(),
+        );
+        Vector3::new(
+            azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+            -azimuth.cos() * elevation.cos(),
+        )
+    }
+
+    /// Directional light shining from the sun
+    pub fn sun(&self) -> Light {
+        Light::Directional(DirectionalLight {
+            direction: -self.sun_direction(),
+            color: self.sun_color(),
+            intensity: self.sun_intensity,
+        })
+    }
+
+    /// Share of the sunlight that makes it through the air, per channel. Rayleigh scattering
+    /// and haze after the paper's appendix, taken at one wavelength for each channel.
+    fn sun_color(&self) -> Color {
+        let zenith = 90.0 - self.sun_elevation.clamp(0.0, 90.0);
+        // relative optical mass, how much more air there is towards the sun than straight up
+        let mass = 1.0 / (zenith.to_radians().cos() + 0.15 * (93.885 - zenith).powf(-1.253));
+        let beta = 0.046_08 * self.turbidity - 0.045_86;
+        let transmittance = |micrometers: f64| {
+            let rayleigh = (-0.008_735 * micrometers.powf(-4.08) * mass).exp();
+            let haze = (-beta * micrometers.powf(-1.3) * mass).exp();
+            (rayleigh * haze) as f32
+        };
+        Color::new(
+            transmittance(0.68),
+            transmittance(0.55),
+            transmittance(0.44),
+        )
+    }
+
+    /// Light coming from the sky in `direction`, the ground below the horizon
+    pub fn radiance(&self, direction: Vector3) -> Color {
+        let direction = direction.normalize();
+        if direction.y < 0.0 {
+            return self.ground();
+        }
+        let model = Model::new(self);
+        let theta = direction.y.clamp(0.0, 1.0).acos();
+        let gamma = direction.dot(&self.sun_direction()).clamp(-1.0, 1.0).acos();
+        model.color(theta, gamma) * self.intensity
+    }
+
+    /// Diffuse ground lit by the sun and, roughly, by the sky straight above
+    fn ground(&self) -> Color {
+        let sun = self.sun_color()
+            * (self.sun_intensity * self.sun_elevation.to_radians().sin().max(0.0) as f32);
+        let sky = Model::new(self).color(0.0, (90.0 - self.sun_elevation).to_radians())
+            * (self.intensity * std::f32::consts::PI);
+        (sun + sky) * (self.ground_albedo / std::f32::consts::PI)
+    }
+}
+
+/// Perez distribution coefficients A to E
+type Perez = [f64; 5];
+
+/// The model for one sun position and turbidity
+struct Model {
+    sun_zenith: f64,
+    luminance: Perez,
+    x: Perez,
+    y: Perez,
+    zenith: (f64, f64, f64),
+}
+
+impl Model {
+    fn new(sky: &Sky) -> Model {
+        let t = sky.turbidity;
+        let sun_zenith = (90.0 - sky.sun_elevation.clamp(0.0, 90.0)).to_radians();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * sun_zenith);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let s = [sun_zenith.powi(3), sun_zenith.powi(2), sun_zenith, 1.0];
+            let row = |r: [f64; 4]| r.iter().zip(&s).map(|(a, b)| a * b).sum::<f64>();
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        Model {
+            sun_zenith,
+            luminance: [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            x: [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            y: [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+            zenith: (zenith_luminance, zenith_x, zenith_y),
+        }
+    }
+
+    /// Perez' sky luminance distribution, `theta` from the zenith, `gamma` from the sun
+    fn perez(c: Perez, theta: f64, gamma: f64) -> f64 {
+        // rays just above the horizon would divide by zero
+        let cos_theta = theta.cos().max(0.01);
+        (1.0 + c[0] * (c[1] / cos_theta).exp())
+            * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+    }
+
+    /// A value at the zenith spread over the sky by its distribution
+    fn distribute(&self, c: Perez, zenith: f64, theta: f64, gamma: f64) -> f64 {
+        zenith * Model::perez(c, theta, gamma) / Model::perez(c, 0.0, self.sun_zenith)
+    }
+
+    fn color(&self, theta: f64, gamma: f64) -> Color {
+        let (zenith_luminance, zenith_x, zenith_y) = self.zenith;
+        let luminance = self.distribute(self.luminance, zenith_luminance, theta, gamma);
+        let x = self.distribute(self.x, zenith_x, theta, gamma);
+        let y = self.distribute(self.y, zenith_y, theta, gamma);
+        xyy_to_rgb(x, y, luminance.max(0.0) * LUMINANCE_SCALE)
+    }
+}
+
+/// CIE xyY to linear sRGB
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return BLACK;
+    }
+    let [r, g, b] = xyz_to_rgb([x * luminance / y, luminance, (1.0 - x - y) * luminance / y]);
+    Color::new(r.max(0.0) as f32, g.max(0.0) as f32, b.max(0.0) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sky(sun_elevation: f64, sun_azimuth: f64) -> Sky {
+        Sky {
+            sun_elevation,
+            sun_azimuth,
+            turbidity: Sky::default_turbidity(),
+            ground_albedo: Sky::default_ground_albedo(),
+            intensity: Sky::default_intensity(),
+            sun_intensity: Sky::default_sun_intensity(),
+        }
+    }
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn sun_direction() {
+        assert!(close(
+            sky(0.0, 0.0).sun_direction(),
+            Vector3::new(0.0, 0.0, -1.0)
+        ));
+        assert!(close(
+            sky(0.0, 90.0).sun_direction(),
+            Vector3::new(1.0, 0.0, 0.0)
+        ));
+        assert!(close(
+            sky(90.0, 45.0).sun_direction(),
+            Vector3::new(0.0, 1.0, 0.0)
+        ));
+    }
+
+    #[test]
+    fn radiance_is_finite_and_positive_all_over_the_sky() {
+        for (elevation, turbidity) in [(0.0, 2.0), (5.0, 3.0), (45.0, 6.0), (90.0, 10.0)] {
+            let sky = Sky {
+                turbidity,
+                ..sky(elevation, 30.0)
+            };
+            for i in 0..=8 {
+                for j in 0..16 {
+                    let (theta, phi) = (i as f64 / 8.0 * PI / 2.0, j as f64 / 16.0 * 2.0 * PI);
+                    let direction = Vector3::new(
+                        theta.sin() * phi.cos(),
+                        theta.cos(),
+                        theta.sin() * phi.sin(),
+                    );
+                    let c = sky.radiance(direction);
+                    for channel in [c.red, c.green, c.blue] {
+                        assert!(
+                            channel.is_finite() && channel >= 0.0,
+                            "{:?} at elevation {}, turbidity {}, direction {:?}",
+                            c,
+                            elevation,
+                            turbidity,
+                            direction
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn low_sun_is_red() {
+        let low = sky(5.0, 0.0).sun_color();
+        assert!(low.red > low.blue, "{:?}", low);
+        let high = sky(80.0, 0.0).sun_color();
+        assert!(high.blue > low.blue);
+    }
+
+    #[test]
+    fn below_the_horizon_is_ground() {
+        let sky = sky(30.0, 0.0);
+        let ground = sky.ground();
+        assert_eq!(sky.radiance(Vector3::new(0.0, -1.0, 0.0)), ground);
+        assert_eq!(sky.radiance(Vector3::new(1.0, -0.1, 0.0)), ground);
+        assert!(ground.red > 0.0 && ground.green > 0.0 && ground.blue > 0.0);
+        let black_ground = Sky {
+            ground_albedo: 0.0,
+            ..sky
+        };
+        assert_eq!(black_ground.radiance(Vector3::new(0.0, -1.0, 0.0)), BLACK);
+    }
+
+    #[test]
+    fn xyy_without_y_is_black() {
+        assert_eq!(xyy_to_rgb(0.3, 0.0, 1.0), BLACK);
+    }
+}
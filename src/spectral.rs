@@ -0,0 +1,215 @@
+//! Spectral rendering: every sample follows a single wavelength of light instead of red,
+//! green and blue at once, so glass with a dispersive index splits white light into colors.
+//!
+//! Colors in the scene stay RGB. A sample's RGB result is weighted by what its wavelength
+//! looks like through the CIE 1931 color matching functions, and the weights are scaled so
+//! white light, all wavelengths equally, averages back to white. Without dispersion a
+//! spectral render converges to the regular one.
+use crate::{entities::Color, sampling::Rng};
+use serde_derive::{Deserialize, Serialize};
+
+/// Nanometers of the Fraunhofer d line, where refractive indices are usually quoted.
+/// Rays without a wavelength see this index.
+const REFERENCE_WAVELENGTH: f64 = 587.6;
+const GOLDEN_RATIO_CONJUGATE: f64 = 0.618_033_988_749_895;
+
+/// Index of refraction, a plain number or one that changes with the wavelength
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(untagged)]
+pub enum RefractiveIndex {
+    Constant(f32),
+    Dispersive(Dispersion),
+}
+
+/// Coefficients take wavelengths in micrometers, the way glass catalogs list them
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum Dispersion {
+    /// `a + b / λ²`, e.g. `{"a": 1.5046, "b": 0.0042}` for BK7 crown glass
+    Cauchy { a: f64, b: f64 },
+    /// `sqrt(1 + Σ b λ² / (λ² - c))`, e.g. `{"b": [1.0396, 0.2318, 1.0105],
+    /// "c": [0.0060, 0.0200, 103.56]}` for BK7
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl RefractiveIndex {
+    /// The index for light of `wavelength` nanometers, at the reference wavelength for `None`
+    pub fn at(&self, wavelength: Option<f64>) -> f32 {
+        let dispersion = match self {
+            RefractiveIndex::Constant(index) => return *index,
+            RefractiveIndex::Dispersive(dispersion) => dispersion,
+        };
+        let micrometers = wavelength.unwrap_or(REFERENCE_WAVELENGTH) / 1000.0;
+        let l2 = micrometers * micrometers;
+        let index = match dispersion {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                (1.0 + b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum::<f64>()).sqrt()
+            }
+        };
+        index as f32
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct Spectral {
+    /// Wavelengths are picked between these, in nanometers
+    pub min_wavelength: f64,
+    pub max_wavelength: f64,
+}
+
+impl Default for Spectral {
+    fn default() -> Spectral {
+        Spectral {
+            min_wavelength: 380.0,
+            max_wavelength: 780.0,
+        }
+    }
+}
+
+impl Spectral {
+    /// Sets up the weights for a render, by averaging the color matching functions
+    /// over the range 1 nm at a time
+    pub fn sampler(&self) -> WavelengthSampler {
+        let steps = (self.max_wavelength - self.min_wavelength).ceil().max(1.0) as u32;
+        let step = (self.max_wavelength - self.min_wavelength) / steps as f64;
+        let mut sum = [0.0; 3];
+        for i in 0..steps {
+            let rgb = wavelength_to_rgb(self.min_wavelength + (i as f64 + 0.5) * step);
+            for (sum, c) in sum.iter_mut().zip(rgb) {
+                *sum += c / steps as f64;
+            }
+        }
+        WavelengthSampler {
+            settings: *self,
+            scale: sum.map(|mean| if mean > 0.0 { 1.0 / mean } else { 0.0 }),
+        }
+    }
+}
+
+pub struct WavelengthSampler {
+    settings: Spectral,
+    /// Brings white light back to white, per channel
+    scale: [f64; 3],
+}
+
+impl WavelengthSampler {
+    /// Wavelength in nanometers for a pixel's `sample`th sample. Every pixel starts at a
+    /// random point of the range and steps along it by the golden ratio, so a few samples
+    /// already cover it evenly.
+    pub fn wavelength(&self, pixel: u32, sample: u32) -> f64 {
+        let start = Rng::new(pixel as u64).next_f64();
+        let u = (start + sample as f64 * GOLDEN_RATIO_CONJUGATE).fract();
+        let Spectral {
+            min_wavelength,
+            max_wavelength,
+        } = self.settings;
+        min_wavelength + u * (max_wavelength - min_wavelength)
+    }
+
+    /// What a sample traced at `wavelength` adds to each channel. Some are negative,
+    /// pure spectral colors lie outside of what RGB can show.
+    pub fn weights(&self, wavelength: f64) -> Color {
+        let rgb = wavelength_to_rgb(wavelength);
+        Color::new(
+            (rgb[0] * self.scale[0]) as f32,
+            (rgb[1] * self.scale[1]) as f32,
+            (rgb[2] * self.scale[2]) as f32,
+        )
+    }
+}
+
+/// CIE 1931 color matching functions after the multi-lobe fit of Wyman, Sloan and Shirley,
+/// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions" (2013)
+pub fn cie_xyz(wavelength: f64) -> [f64; 3] {
+    let lobe = |mean: f64, below: f64, above: f64| {
+        let t = (wavelength - mean) / if wavelength < mean { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    [
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    ]
+}
+
+/// CIE XYZ to linear sRGB, negative outside of the sRGB gamut
+pub fn xyz_to_rgb([x, y, z]: [f64; 3]) -> [f64; 3] {
+    [
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    ]
+}
+
+fn wavelength_to_rgb(wavelength: f64) -> [f64; 3] {
+    xyz_to_rgb(cie_xyz(wavelength))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+        c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+    };
+
+    #[test]
+    fn white_averages_to_white() {
+        let sampler = Spectral::default().sampler();
+        let samples = 4096;
+        let mut sum = [0.0; 3];
+        for sample in 0..samples {
+            let weights = sampler.weights(sampler.wavelength(7, sample));
+            sum[0] += weights.red as f64;
+            sum[1] += weights.green as f64;
+            sum[2] += weights.blue as f64;
+        }
+        for channel in sum {
+            let mean = channel / samples as f64;
+            assert!((mean - 1.0).abs() < 0.01, "{:?}", sum);
+        }
+    }
+
+    #[test]
+    fn wavelengths_stay_in_range() {
+        let settings = Spectral {
+            min_wavelength: 400.0,
+            max_wavelength: 500.0,
+        };
+        let sampler = settings.sampler();
+        for sample in 0..100 {
+            let wavelength = sampler.wavelength(3, sample);
+            assert!((400.0..500.0).contains(&wavelength), "{}", wavelength);
+        }
+    }
+
+    #[test]
+    fn bk7_sellmeier() {
+        let index = RefractiveIndex::Dispersive(BK7);
+        assert!((index.at(Some(587.6)) - 1.5168).abs() < 1e-4);
+        // rays without a wavelength see the d line
+        assert_eq!(index.at(None), index.at(Some(587.6)));
+        // blue bends more than red
+        assert!(index.at(Some(450.0)) > index.at(Some(650.0)));
+    }
+
+    #[test]
+    fn bk7_cauchy() {
+        let index = RefractiveIndex::Dispersive(Dispersion::Cauchy {
+            a: 1.5046,
+            b: 0.0042,
+        });
+        assert!((index.at(Some(587.6)) - 1.5168).abs() < 1e-3);
+        assert!(index.at(Some(450.0)) > index.at(Some(650.0)));
+    }
+
+    #[test]
+    fn constant_index() {
+        let index = RefractiveIndex::Constant(1.33);
+        assert_eq!(index.at(Some(400.0)), 1.33);
+        assert_eq!(index.at(None), 1.33);
+    }
+}
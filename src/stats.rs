@@ -0,0 +1,315 @@
+//! Render statistics and the live progress line.
+use serde_derive::Serialize;
+use termion::{clear, color, style};
+
+use std::{
+    cell::Cell,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+/// Redrawing the progress line more often than this only slows the render down
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Counters bumped while tracing.
+/// They live in `Cell`s so the `&self` tracing code can update them.
+#[derive(Default, Debug)]
+pub struct RenderStats {
+    pub primary_rays: Cell<u64>,
+    pub shadow_rays: Cell<u64>,
+    pub reflection_rays: Cell<u64>,
+    pub refraction_rays: Cell<u64>,
+    pub intersection_tests: Cell<u64>,
+    pub max_depth: Cell<u32>,
+}
+
+fn bump(counter: &Cell<u64>, by: u64) {
+    counter.set(counter.get() + by);
+}
+
+impl RenderStats {
+    pub fn primary_ray(&self) {
+        bump(&self.primary_rays, 1);
+    }
+    pub fn shadow_ray(&self) {
+        bump(&self.shadow_rays, 1);
+    }
+    pub fn reflection_ray(&self) {
+        bump(&self.reflection_rays, 1);
+    }
+    pub fn refraction_ray(&self) {
+        bump(&self.refraction_rays, 1);
+    }
+    pub fn intersection_tests(&self, count: usize) {
+        bump(&self.intersection_tests, count as u64);
+    }
+    pub fn depth_reached(&self, depth: u32) {
+        self.max_depth.set(self.max_depth.get().max(depth));
+    }
+
+    pub fn total_rays(&self) -> u64 {
+        self.primary_rays.get()
+            + self.shadow_rays.get()
+            + self.reflection_rays.get()
+            + self.refraction_rays.get()
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Phase {
+    pub name: String,
+    pub seconds: f64,
+}
+
+/// Everything worth knowing after a render, printable or dumpable as JSON
+#[derive(Serialize, Debug)]
+pub struct StatsReport {
+    pub primary_rays: u64,
+    pub shadow_rays: u64,
+    pub reflection_rays: u64,
+    pub refraction_rays: u64,
+    pub total_rays: u64,
+    pub intersection_tests: u64,
+    pub max_recursion_depth: u32,
+    pub rays_per_second: f64,
+    pub phases: Vec<Phase>,
+}
+
+impl StatsReport {
+    pub fn new(stats: &RenderStats, phases: Vec<Phase>) -> StatsReport {
+        let seconds: f64 = phases.iter().map(|p| p.seconds).sum();
+        StatsReport {
+            primary_rays: stats.primary_rays.get(),
+            shadow_rays: stats.shadow_rays.get(),
+            reflection_rays: stats.reflection_rays.get(),
+            refraction_rays: stats.refraction_rays.get(),
+            total_rays: stats.total_rays(),
+            intersection_tests: stats.intersection_tests.get(),
+            max_recursion_depth: stats.max_depth.get(),
+            rays_per_second: stats.total_rays() as f64 / seconds.max(f64::EPSILON),
+            phases,
+        }
+    }
+
+    pub fn print(&self) {
+        let row = |name: &str, value: String| println!("  {:<20}{:>16}", name, value);
+        println!("{}render statistics{}", style::Bold, style::Reset);
+        row("primary rays", self.primary_rays.to_string());
+        row("shadow rays", self.shadow_rays.to_string());
+        row("reflection rays", self.reflection_rays.to_string());
+        row("refraction rays", self.refraction_rays.to_string());
+        row("total rays", self.total_rays.to_string());
+        row("intersection tests", self.intersection_tests.to_string());
+        row("max recursion", self.max_recursion_depth.to_string());
+        row("rays per second", format_rate(self.rays_per_second));
+        for phase in &self.phases {
+            row(&phase.name, format!("{:.3}s", phase.seconds));
+        }
+    }
+}
+
+fn format_rate(per_second: f64) -> String {
+    if per_second >= 1e6 {
+        format!("{:.2}M", per_second / 1e6)
+    } else if per_second >= 1e3 {
+        format!("{:.1}k", per_second / 1e3)
+    } else {
+        format!("{:.0}", per_second)
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Single self-updating line with a bar, ETA, ray throughput and tiles done
+pub struct Progress {
+    enabled: bool,
+    start: Instant,
+    last_draw: Option<Instant>,
+    pub tiles_done: u64,
+    pub tiles_total: u64,
+    /// Time budgeted renders are done when the time is up, whatever the tile count says
+    pub time_limit: Option<Duration>,
+}
+
+impl Progress {
+    /// Draws only when stdout is a terminal, redirected output stays clean
+    pub fn new(tiles_total: u64) -> Progress {
+        Progress {
+            enabled: termion::is_tty(&io::stdout()),
+            start: Instant::now(),
+            last_draw: None,
+            tiles_done: 0,
+            tiles_total,
+            time_limit: None,
+        }
+    }
+
+    pub fn hidden() -> Progress {
+        Progress {
+            enabled: false,
+            ..Progress::new(0)
+        }
+    }
+
+    pub fn fraction(&self) -> f64 {
+        let tiles = self.tiles_done as f64 / self.tiles_total.max(1) as f64;
+        let time = self.time_limit.map_or(0.0, |limit| {
+            self.start.elapsed().as_secs_f64() / limit.as_secs_f64().max(f64::EPSILON)
+        });
+        tiles.max(time).min(1.0)
+    }
+
+    pub fn tile_done(&mut self, stats: &RenderStats) {
+        self.tiles_done += 1;
+        if !self.enabled {
+            return;
+        }
+        if let Some(last) = self.last_draw {
+            if last.elapsed() < REDRAW_INTERVAL {
+                return;
+            }
+        }
+        self.last_draw = Some(Instant::now());
+        self.draw(stats);
+    }
+
+    fn draw(&self, stats: &RenderStats) {
+        const WIDTH: usize = 30;
+        let fraction = self.fraction();
+        let elapsed = self.start.elapsed();
+        let filled = (fraction * WIDTH as f64) as usize;
+        let eta = if fraction > 0.0 {
+            format_duration(elapsed.mul_f64((1.0 - fraction) / fraction))
+        } else {
+            "--:--:--".to_string()
+        };
+        let rays_per_second = stats.total_rays() as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+
+        let mut out = io::stdout();
+        let _ = write!(
+            out,
+            "\r{}{}[{}{}]{} {:5.1}%  ETA {}  {} rays/s  tiles {}/{}",
+            clear::CurrentLine,
+            color::Fg(color::Green),
+            "#".repeat(filled),
+            "-".repeat(WIDTH - filled),
+            color::Fg(color::Reset),
+            fraction * 100.0,
+            eta,
+            format_rate(rays_per_second),
+            self.tiles_done,
+            self.tiles_total,
+        );
+        let _ = out.flush();
+    }
+
+    /// Clears the progress line, so the next output starts on a clean line
+    pub fn finish(&self) {
+        if self.enabled && self.last_draw.is_some() {
+            print!("\r{}", clear::CurrentLine);
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+/// Times the steps of a run one after another
+#[derive(Debug)]
+pub struct PhaseTimer {
+    start: Instant,
+    pub phases: Vec<Phase>,
+}
+
+impl Default for PhaseTimer {
+    fn default() -> PhaseTimer {
+        PhaseTimer {
+            start: Instant::now(),
+            phases: vec![],
+        }
+    }
+}
+
+impl PhaseTimer {
+    /// Records the time since the previous phase ended
+    pub fn end(&mut self, name: &str) {
+        self.phases.push(Phase {
+            name: name.to_string(),
+            seconds: self.start.elapsed().as_secs_f64(),
+        });
+        self.start = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::lit_sphere, entities::Material, rendering::WHITE};
+
+    #[test]
+    fn counters_add_up() {
+        let stats = RenderStats::default();
+        stats.primary_ray();
+        stats.primary_ray();
+        stats.shadow_ray();
+        stats.reflection_ray();
+        stats.refraction_ray();
+        stats.intersection_tests(7);
+        stats.depth_reached(3);
+        stats.depth_reached(1);
+        assert_eq!(stats.total_rays(), 5);
+        assert_eq!(stats.intersection_tests.get(), 7);
+        assert_eq!(stats.max_depth.get(), 3);
+    }
+
+    #[test]
+    fn renders_count_their_rays() {
+        let scene = lit_sphere(Material::diffuse(WHITE)).size(9, 9).build();
+        scene.render(&mut Progress::hidden());
+        let stats = &scene.stats;
+        assert_eq!(stats.primary_rays.get(), 81);
+        // a shadow ray for every pixel that sees the sphere
+        assert!(stats.shadow_rays.get() > 0 && stats.shadow_rays.get() < 81);
+        assert!(stats.intersection_tests.get() > 81);
+    }
+
+    #[test]
+    fn report_json() {
+        let stats = RenderStats::default();
+        stats.primary_ray();
+        stats.shadow_ray();
+        let phases = vec![
+            Phase {
+                name: "render".to_string(),
+                seconds: 1.5,
+            },
+            Phase {
+                name: "save".to_string(),
+                seconds: 0.5,
+            },
+        ];
+        let json = serde_json::to_string_pretty(&StatsReport::new(&stats, phases)).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["primary_rays"], 1);
+        assert_eq!(parsed["total_rays"], 2);
+        assert_eq!(parsed["rays_per_second"], 1.0);
+        assert_eq!(parsed["phases"][1]["name"], "save");
+    }
+
+    #[test]
+    fn rates() {
+        assert_eq!(format_rate(12.0), "12");
+        assert_eq!(format_rate(1_500.0), "1.5k");
+        assert_eq!(format_rate(2_340_000.0), "2.34M");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "01:02:03");
+    }
+
+    #[test]
+    fn progress_fraction() {
+        let mut progress = Progress::hidden();
+        progress.tiles_total = 4;
+        progress.tile_done(&RenderStats::default());
+        assert_eq!(progress.fraction(), 0.25);
+    }
+}
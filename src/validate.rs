@@ -0,0 +1,909 @@
+//! Sanity checks for values serde happily accepts but the renderer chokes on,
+//! like a negative radius or a zero-length normal, which quietly end up as NaNs or black images.
+use crate::{
+    conductor::Conductor,
+    entities::{Color, Coloration, Element, Light, Material, Motion, SurfaceType},
+    relief::Relief,
+    scene::Scene,
+    spectral::{Dispersion, RefractiveIndex, Spectral},
+    vector::Vector3,
+    volume::Medium,
+};
+use termion::color;
+
+use std::fmt;
+
+/// Wavelengths across the spectral range a dispersive index is checked at
+const INDEX_SAMPLES: u32 = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Renders, but most likely not what was meant
+    Warning,
+    /// Renders garbage or nothing at all
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Where in the scene file, e.g. `elements[3].Sphere.radius`
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "{}warning", color::Fg(color::Yellow))?,
+            Severity::Error => write!(f, "{}error", color::Fg(color::Red))?,
+        }
+        write!(
+            f,
+            "{}: {}: {}",
+            color::Fg(color::Reset),
+            self.path,
+            self.message
+        )
+    }
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
+
+/// Collects diagnostics while walking the scene
+#[derive(Default)]
+struct Checker {
+    diagnostics: Vec<Diagnostic>,
+    /// The scene's wavelength range, when it renders spectrally and the range is sound
+    spectral: Option<Spectral>,
+}
+
+impl Checker {
+    fn report(&mut self, severity: Severity, path: &str, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            path: path.to_string(),
+            message,
+        });
+    }
+
+    fn error_if(&mut self, failed: bool, path: &str, message: String) {
+        if failed {
+            self.report(Severity::Error, path, message);
+        }
+    }
+
+    fn warn_if(&mut self, failed: bool, path: &str, message: String) {
+        if failed {
+            self.report(Severity::Warning, path, message);
+        }
+    }
+
+    fn direction(&mut self, v: Vector3, path: &str) {
+        let length = v.length();
+        self.error_if(
+            !length.is_finite() || length < 1e-9,
+            path,
+            "has to be a non-zero vector".to_string(),
+        );
+    }
+
+    fn color(&mut self, c: Color, path: &str) {
+        self.warn_if(
+            c.red < 0.0 || c.green < 0.0 || c.blue < 0.0,
+            path,
+            format!(
+                "negative components ({}, {}, {}) subtract light",
+                c.red, c.green, c.blue
+            ),
+        );
+    }
+
+    fn material(&mut self, material: &Material, path: &str) {
+        if let Coloration::Color(c) = material.coloration {
+            self.color(c, &format!("{}.coloration.Color", path));
+        }
+        if let Some(emission) = &material.emission {
+            if let Coloration::Color(c) = emission.coloration {
+                self.color(c, &format!("{}.emission.coloration.Color", path));
+            }
+            self.warn_if(
+                emission.strength < 0.0,
+                &format!("{}.emission.strength", path),
+                format!(
+                    "a negative strength swallows light, got {}",
+                    emission.strength
+                ),
+            );
+        }
+        if let Some(medium) = &material.medium {
+            self.medium(medium, &format!("{}.medium", path));
+            self.warn_if(
+                !matches!(material.surface, SurfaceType::Refractive { .. }),
+                &format!("{}.medium", path),
+                "only rays through a Refractive surface get into the medium".to_string(),
+            );
+        }
+        match &material.relief {
+            Some(Relief::NormalMap { strength, .. }) => self.warn_if(
+                *strength < 0.0,
+                &format!("{}.relief.NormalMap.strength", path),
+                format!(
+                    "a negative strength turns the normals inside out, got {}",
+                    strength
+                ),
+            ),
+            Some(Relief::BumpMap { strength, .. }) => self.warn_if(
+                *strength < 0.0,
+                &format!("{}.relief.BumpMap.strength", path),
+                format!(
+                    "a negative strength swaps bumps and dents, got {}",
+                    strength
+                ),
+            ),
+            None => {}
+        }
+        if let Some(cutout) = &material.cutout {
+            self.warn_if(
+                !(0.0..=1.0).contains(&cutout.threshold),
+                &format!("{}.cutout.threshold", path),
+                format!("expected 0 to 1, got {}", cutout.threshold),
+            );
+            self.warn_if(
+                cutout.mask.is_none() && matches!(material.coloration, Coloration::Color(_)),
+                &format!("{}.cutout", path),
+                "a plain color is opaque everywhere, nothing gets cut out".to_string(),
+            );
+        }
+        let albedo = material.albedo;
+        self.error_if(
+            albedo < 0.0,
+            &format!("{}.albedo", path),
+            format!("has to be at least 0, got {}", albedo),
+        );
+        self.warn_if(
+            albedo > 1.0,
+            &format!("{}.albedo", path),
+            format!(
+                "above 1 reflects more light than hits the surface, got {}",
+                albedo
+            ),
+        );
+        match material.surface {
+            SurfaceType::Diffuse => {}
+            SurfaceType::Conductor(Conductor::Custom { eta, k }) => {
+                self.error_if(
+                    eta.red <= 0.0 || eta.green <= 0.0 || eta.blue <= 0.0,
+                    &format!("{}.surface.Conductor.Custom.eta", path),
+                    format!("has to be positive, got {:?}", eta),
+                );
+                self.error_if(
+                    k.red < 0.0 || k.green < 0.0 || k.blue < 0.0,
+                    &format!("{}.surface.Conductor.Custom.k", path),
+                    format!("has to be at least 0, got {:?}", k),
+                );
+            }
+            SurfaceType::Conductor(_) => {}
+            SurfaceType::Reflective { reflectivity } => self.warn_if(
+                !(0.0..=1.0).contains(&reflectivity),
+                &format!("{}.surface.Reflective.reflectivity", path),
+                format!("expected 0 to 1, got {}", reflectivity),
+            ),
+            SurfaceType::Refractive {
+                index,
+                transparency,
+            } => {
+                self.index(index, &format!("{}.surface.Refractive.index", path));
+                self.warn_if(
+                    !(0.0..=1.0).contains(&transparency),
+                    &format!("{}.surface.Refractive.transparency", path),
+                    format!("expected 0 to 1, got {}", transparency),
+                );
+            }
+        }
+    }
+
+    /// An index below 1 or a Sellmeier pole anywhere the scene's rays can see the index.
+    /// Without spectral rendering that's only at the reference wavelength.
+    fn index(&mut self, index: RefractiveIndex, path: &str) {
+        let spectral = match self.spectral {
+            Some(spectral) => spectral,
+            None => {
+                let n = index.at(None);
+                self.error_if(
+                    n.is_nan() || n < 1.0,
+                    path,
+                    format!("has to be at least 1, got {}", n),
+                );
+                return;
+            }
+        };
+        let (min, max) = (spectral.min_wavelength, spectral.max_wavelength);
+        if let RefractiveIndex::Dispersive(Dispersion::Sellmeier { c, .. }) = index {
+            // each term blows up where λ² = c, with λ in micrometers
+            let range = (min / 1000.0).powi(2)..=(max / 1000.0).powi(2);
+            if let Some(pole) = c.iter().find(|c| range.contains(c)) {
+                self.report(
+                    Severity::Error,
+                    path,
+                    format!(
+                        "has a pole at {:.1} nm, inside the spectral range {} to {} nm",
+                        pole.sqrt() * 1000.0,
+                        min,
+                        max
+                    ),
+                );
+                return;
+            }
+        }
+        for i in 0..=INDEX_SAMPLES {
+            let wavelength = min + (max - min) * i as f64 / INDEX_SAMPLES as f64;
+            let n = index.at(Some(wavelength));
+            if !n.is_finite() || n < 1.0 {
+                self.report(
+                    Severity::Error,
+                    path,
+                    format!("has to be at least 1, got {} at {} nm", n, wavelength),
+                );
+                return;
+            }
+        }
+    }
+
+    fn coefficient(&mut self, c: Color, path: &str) {
+        self.error_if(
+            c.red < 0.0 || c.green < 0.0 || c.blue < 0.0,
+            path,
+            format!(
+                "has to be at least 0 in every channel, got ({}, {}, {})",
+                c.red, c.green, c.blue
+            ),
+        );
+    }
+
+    fn anisotropy(&mut self, g: f64, path: &str) {
+        self.error_if(
+            g.is_nan() || g <= -1.0 || g >= 1.0,
+            path,
+            format!("expected more than -1 and less than 1, got {}", g),
+        );
+    }
+
+    fn medium(&mut self, medium: &Medium, path: &str) {
+        self.coefficient(medium.absorption, &format!("{}.absorption", path));
+        self.coefficient(medium.scattering, &format!("{}.scattering", path));
+        self.anisotropy(medium.anisotropy, &format!("{}.anisotropy", path));
+    }
+
+    fn motion(&mut self, motion: &Option<Motion>, path: &str) {
+        if let Some(Motion::Keys(keys)) = motion {
+            self.error_if(
+                keys.windows(2).any(|w| w[0].time >= w[1].time),
+                &format!("{}.motion.Keys", path),
+                "keys have to be in time order".to_string(),
+            );
+        }
+    }
+
+    fn element(&mut self, element: &Element, path: &str) {
+        match element {
+            Element::Sphere(s) => {
+                let path = format!("{}.Sphere", path);
+                self.error_if(
+                    s.radius.is_nan() || s.radius <= 0.0,
+                    &format!("{}.radius", path),
+                    format!("has to be positive, got {}", s.radius),
+                );
+                self.material(&s.material, &format!("{}.material", path));
+                self.motion(&s.motion, &path);
+            }
+            Element::Plane(p) => {
+                let path = format!("{}.Plane", path);
+                self.direction(p.normal, &format!("{}.normal", path));
+                self.material(&p.material, &format!("{}.material", path));
+                self.motion(&p.motion, &path);
+            }
+        }
+    }
+
+    fn light(&mut self, light: &Light, path: &str) {
+        let (path, color, intensity) = match light {
+            Light::Directional(d) => {
+                let path = format!("{}.Directional", path);
+                self.direction(d.direction, &format!("{}.direction", path));
+                (path, d.color, d.intensity)
+            }
+            Light::Spherical(s) => (format!("{}.Spherical", path), s.color, s.intensity),
+            Light::Spot(s) => {
+                let path = format!("{}.Spot", path);
+                self.direction(s.direction, &format!("{}.direction", path));
+                self.error_if(
+                    !(s.outer_angle > 0.0 && s.outer_angle < 90.0),
+                    &format!("{}.outer_angle", path),
+                    format!(
+                        "expected more than 0 and less than 90 degrees, got {}",
+                        s.outer_angle
+                    ),
+                );
+                self.warn_if(
+                    s.inner_angle > s.outer_angle,
+                    &format!("{}.inner_angle", path),
+                    format!(
+                        "wider than outer_angle {} leaves the cone with a hard edge, got {}",
+                        s.outer_angle, s.inner_angle
+                    ),
+                );
+                if let Some(Coloration::Color(c)) = s.gobo {
+                    self.color(c, &format!("{}.gobo.Color", path));
+                }
+                (path, s.color, s.intensity)
+            }
+        };
+        self.color(color, &format!("{}.color", path));
+        self.warn_if(
+            intensity < 0.0,
+            &format!("{}.intensity", path),
+            format!("a negative intensity darkens the scene, got {}", intensity),
+        );
+    }
+}
+
+impl Scene {
+    /// Everything suspicious about the scene, errors first
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut check = Checker::default();
+
+        if let Some(spectral) = self.spectral {
+            let (min, max) = (spectral.min_wavelength, spectral.max_wavelength);
+            check.error_if(
+                min.is_nan() || min <= 0.0,
+                "spectral.min_wavelength",
+                format!("has to be positive, got {}", min),
+            );
+            check.error_if(
+                max.is_nan() || max <= min,
+                "spectral.max_wavelength",
+                format!("has to be above min_wavelength {}, got {}", min, max),
+            );
+            if min > 0.0 && max > min && max.is_finite() {
+                check.spectral = Some(spectral);
+            }
+        }
+
+        check.error_if(
+            self.width == 0,
+            "width",
+            "the image needs at least one pixel".to_string(),
+        );
+        check.error_if(
+            self.height == 0,
+            "height",
+            "the image needs at least one pixel".to_string(),
+        );
+        check.error_if(
+            !(self.fov > 0.0 && self.fov < 180.0),
+            "fov",
+            format!(
+                "expected more than 0 and less than 180 degrees, got {}",
+                self.fov
+            ),
+        );
+        check.warn_if(
+            self.max_recursion_depth == 0,
+            "max_recursion_depth",
+            "0 still shades what the camera sees but leaves reflections and refractions black"
+                .to_string(),
+        );
+        check.warn_if(
+            self.shadow_bias < 0.0,
+            "shadow_bias",
+            format!(
+                "a negative bias puts shadow acne everywhere, got {}",
+                self.shadow_bias
+            ),
+        );
+
+        let camera = self.camera;
+        check.warn_if(
+            camera.shutter_close < camera.shutter_open,
+            "camera.shutter_close",
+            format!(
+                "closes before the shutter opens at {}, got {}",
+                camera.shutter_open, camera.shutter_close
+            ),
+        );
+        check.direction(camera.direction, "camera.direction");
+        check.direction(camera.up, "camera.up");
+        check.error_if(
+            camera.direction.cross(&camera.up).length() < 1e-9,
+            "camera.up",
+            "can't point the same way as camera.direction".to_string(),
+        );
+
+        check.warn_if(
+            self.elements.is_empty(),
+            "elements",
+            "nothing to render".to_string(),
+        );
+        for (i, element) in self.elements.iter().enumerate() {
+            check.element(element, &format!("elements[{}]", i));
+            check.warn_if(
+                self.path_tracing.is_some()
+                    && matches!(element, Element::Plane(p) if p.material.emission.is_some()),
+                &format!("elements[{}].Plane.material.emission", i),
+                "only emissive spheres are sampled as lights, a glowing plane lights the scene \
+                 through random bounces alone and stays noisy"
+                    .to_string(),
+            );
+        }
+        check.warn_if(
+            self.lights.is_empty()
+                && self.sky.is_none()
+                && self
+                    .elements
+                    .iter()
+                    .all(|e| e.material().emission.is_none()),
+            "lights",
+            "without lights or emissive elements everything is black".to_string(),
+        );
+        for (i, light) in self.lights.iter().enumerate() {
+            check.light(light, &format!("lights[{}]", i));
+        }
+
+        if let Some(sky) = self.sky {
+            check.error_if(
+                !(0.0..=90.0).contains(&sky.sun_elevation),
+                "sky.sun_elevation",
+                format!(
+                    "the model needs the sun 0 to 90 degrees above the horizon, got {}",
+                    sky.sun_elevation
+                ),
+            );
+            check.warn_if(
+                !(2.0..=10.0).contains(&sky.turbidity),
+                "sky.turbidity",
+                format!(
+                    "the model is only fitted for 2 to 10, got {}",
+                    sky.turbidity
+                ),
+            );
+            check.warn_if(
+                !(0.0..=1.0).contains(&sky.ground_albedo),
+                "sky.ground_albedo",
+                format!("expected 0 to 1, got {}", sky.ground_albedo),
+            );
+        }
+
+        if let Some(fog) = self.fog {
+            check.error_if(
+                fog.density.is_nan() || fog.density < 0.0,
+                "fog.density",
+                format!("has to be at least 0, got {}", fog.density),
+            );
+            check.error_if(
+                !(0.0..=1.0).contains(&fog.scattering),
+                "fog.scattering",
+                format!("expected 0 to 1, got {}", fog.scattering),
+            );
+            check.color(fog.color, "fog.color");
+            check.anisotropy(fog.anisotropy, "fog.anisotropy");
+        }
+        if let Some(volumes) = self.volumes {
+            check.error_if(
+                volumes.step_size.is_nan() || volumes.step_size <= 0.0,
+                "volumes.step_size",
+                format!("has to be positive, got {}", volumes.step_size),
+            );
+            check.error_if(
+                volumes.max_steps == 0,
+                "volumes.max_steps",
+                "needs at least one step".to_string(),
+            );
+        }
+        for (i, grid) in self.volume_grids.iter().enumerate() {
+            let at = |what: &str| format!("volume_grids[{}].{}", i, what);
+            let scale = grid.transform.scale;
+            check.error_if(
+                [scale.x, scale.y, scale.z]
+                    .iter()
+                    .any(|c| c.is_nan() || *c <= 0.0),
+                &at("transform.scale"),
+                format!("has to be positive, got {:?}", scale),
+            );
+            check.error_if(
+                grid.density.is_nan() || grid.density < 0.0,
+                &at("density"),
+                format!("has to be at least 0, got {}", grid.density),
+            );
+            check.color(grid.albedo, &at("albedo"));
+            check.warn_if(
+                grid.albedo.red > 1.0 || grid.albedo.green > 1.0 || grid.albedo.blue > 1.0,
+                &at("albedo"),
+                "above 1 the smoke scatters more light than reaches it".to_string(),
+            );
+            check.anisotropy(grid.anisotropy, &at("anisotropy"));
+        }
+
+        if let Some(progressive) = self.progressive {
+            check.error_if(
+                progressive.samples == Some(0),
+                "progressive.samples",
+                "0 samples never finish a pixel".to_string(),
+            );
+        }
+        if let Some(denoise) = self.denoise {
+            check.warn_if(
+                !(0.0..=1.0).contains(&denoise.strength),
+                "denoise.strength",
+                format!("expected 0 to 1, got {}", denoise.strength),
+            );
+        }
+
+        let mut diagnostics = check.diagnostics;
+        diagnostics.sort_by_key(|d| d.severity != Severity::Error);
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::lit_sphere,
+        camera::Camera,
+        denoise::Denoiser,
+        entities::SurfaceType,
+        grid::{DensityGrid, Transform, VolumeGrid},
+        point::Point,
+        progressive::Progressive,
+        rendering::WHITE,
+        sky::Sky,
+        volume::{Fog, Volumes},
+    };
+
+    fn scene() -> Scene {
+        lit_sphere(Material::diffuse(WHITE)).build()
+    }
+
+    fn assert_reported(scene: &Scene, severity: Severity, path: &str) {
+        let diagnostics = scene.validate();
+        assert!(
+            diagnostics
+                .iter()
+                .any(|d| d.severity == severity && d.path == path),
+            "no {:?} at {} in {:?}",
+            severity,
+            path,
+            diagnostics
+        );
+    }
+
+    fn clear_sky() -> Sky {
+        Sky {
+            sun_elevation: 45.0,
+            sun_azimuth: 0.0,
+            turbidity: 3.0,
+            ground_albedo: 0.3,
+            intensity: 1.0,
+            sun_intensity: 5.0,
+        }
+    }
+
+    fn thin_fog() -> Fog {
+        Fog {
+            density: 0.1,
+            color: WHITE,
+            scattering: 0.5,
+            anisotropy: 0.0,
+        }
+    }
+
+    fn volume_grid() -> VolumeGrid {
+        VolumeGrid {
+            grid: DensityGrid {
+                path: "smoke.grid".into(),
+                dims: [1, 1, 1],
+                densities: vec![1.0],
+                max: 1.0,
+            },
+            transform: Transform::default(),
+            density: 1.0,
+            albedo: WHITE,
+            anisotropy: 0.0,
+        }
+    }
+
+    #[test]
+    fn a_plain_scene_is_fine() {
+        let mut scene = scene();
+        scene.sky = Some(clear_sky());
+        scene.fog = Some(thin_fog());
+        scene.volumes = Some(Volumes::default());
+        scene.volume_grids.push(volume_grid());
+        scene.progressive = Some(Progressive::default());
+        scene.denoise = Some(Denoiser::default());
+        let diagnostics = scene.validate();
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn errors_come_first() {
+        let mut scene = scene();
+        scene.shadow_bias = -1.0;
+        scene.width = 0;
+        let diagnostics = scene.validate();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[1].severity, Severity::Warning);
+        assert!(has_errors(&diagnostics));
+    }
+
+    #[test]
+    fn scene_settings() {
+        let scene = lit_sphere(Material::diffuse(WHITE)).size(0, 10).build();
+        assert_reported(&scene, Severity::Error, "width");
+        let scene = lit_sphere(Material::diffuse(WHITE)).fov(180.0).build();
+        assert_reported(&scene, Severity::Error, "fov");
+        let scene = lit_sphere(Material::diffuse(WHITE))
+            .shadow_bias(-1.0)
+            .build();
+        assert_reported(&scene, Severity::Warning, "shadow_bias");
+    }
+
+    #[test]
+    fn no_recursion_is_only_a_warning() {
+        let scene = lit_sphere(Material::diffuse(WHITE))
+            .max_recursion_depth(0)
+            .build();
+        assert_reported(&scene, Severity::Warning, "max_recursion_depth");
+        assert!(!has_errors(&scene.validate()));
+    }
+
+    #[test]
+    fn camera() {
+        let parallel = Camera {
+            up: Vector3::new(0.0, 0.0, -1.0),
+            ..Camera::default()
+        };
+        let scene = lit_sphere(Material::diffuse(WHITE))
+            .camera(parallel)
+            .build();
+        assert_reported(&scene, Severity::Error, "camera.up");
+        let backwards = Camera {
+            shutter_open: 1.0,
+            ..Camera::default()
+        };
+        let scene = lit_sphere(Material::diffuse(WHITE))
+            .camera(backwards)
+            .build();
+        assert_reported(&scene, Severity::Warning, "camera.shutter_close");
+    }
+
+    #[test]
+    fn elements() {
+        let scene = lit_sphere(Material::diffuse(WHITE))
+            .sphere(Point::zero(), -1.0, Material::diffuse(WHITE))
+            .build();
+        assert_reported(&scene, Severity::Error, "elements[1].Sphere.radius");
+        let scene = lit_sphere(Material::diffuse(WHITE))
+            .plane(Point::zero(), Vector3::zero(), Material::diffuse(WHITE))
+            .build();
+        assert_reported(&scene, Severity::Error, "elements[1].Plane.normal");
+        let scene = Scene::builder()
+            .light(Light::directional(Vector3::new(0.0, -1.0, 0.0), WHITE, 1.0))
+            .build();
+        assert_reported(&scene, Severity::Warning, "elements");
+    }
+
+    #[test]
+    fn materials() {
+        let mut material = Material::diffuse(WHITE);
+        material.albedo = -0.5;
+        let scene = lit_sphere(material).build();
+        assert_reported(
+            &scene,
+            Severity::Error,
+            "elements[0].Sphere.material.albedo",
+        );
+        let scene = lit_sphere(Material::reflective(WHITE, 1.5)).build();
+        assert_reported(
+            &scene,
+            Severity::Warning,
+            "elements[0].Sphere.material.surface.Reflective.reflectivity",
+        );
+        let scene = lit_sphere(Material::refractive(WHITE, 0.5, 1.0)).build();
+        assert_reported(
+            &scene,
+            Severity::Error,
+            "elements[0].Sphere.material.surface.Refractive.index",
+        );
+    }
+
+    #[test]
+    fn lights() {
+        let spot = Light::spot(
+            Point::zero(),
+            Vector3::new(0.0, 0.0, -1.0),
+            WHITE,
+            1.0,
+            10.0,
+            90.0,
+        );
+        let scene = lit_sphere(Material::diffuse(WHITE)).light(spot).build();
+        assert_reported(&scene, Severity::Error, "lights[1].Spot.outer_angle");
+        let dark = Light::spherical(Point::zero(), WHITE, -1.0);
+        let scene = lit_sphere(Material::diffuse(WHITE)).light(dark).build();
+        assert_reported(&scene, Severity::Warning, "lights[1].Spherical.intensity");
+        let scene = Scene::builder()
+            .sphere(Point::zero(), 1.0, Material::diffuse(WHITE))
+            .build();
+        assert_reported(&scene, Severity::Warning, "lights");
+    }
+
+    #[test]
+    fn sky() {
+        let below = Sky {
+            sun_elevation: -10.0,
+            ..clear_sky()
+        };
+        let scene = lit_sphere(Material::diffuse(WHITE)).sky(below).build();
+        assert_reported(&scene, Severity::Error, "sky.sun_elevation");
+        let hazy = Sky {
+            turbidity: 20.0,
+            ..clear_sky()
+        };
+        let scene = lit_sphere(Material::diffuse(WHITE)).sky(hazy).build();
+        assert_reported(&scene, Severity::Warning, "sky.turbidity");
+    }
+
+    #[test]
+    fn fog() {
+        let negative = Fog {
+            density: -0.1,
+            ..thin_fog()
+        };
+        let scene = lit_sphere(Material::diffuse(WHITE)).fog(negative).build();
+        assert_reported(&scene, Severity::Error, "fog.density");
+        let tinted = Fog {
+            color: Color::new(1.0, -1.0, 1.0),
+            ..thin_fog()
+        };
+        let scene = lit_sphere(Material::diffuse(WHITE)).fog(tinted).build();
+        assert_reported(&scene, Severity::Warning, "fog.color");
+    }
+
+    #[test]
+    fn volumes() {
+        let mut scene = scene();
+        scene.volumes = Some(Volumes {
+            step_size: 0.0,
+            max_steps: 0,
+        });
+        assert_reported(&scene, Severity::Error, "volumes.step_size");
+        assert_reported(&scene, Severity::Error, "volumes.max_steps");
+    }
+
+    #[test]
+    fn volume_grids() {
+        let mut scene = scene();
+        let mut grid = volume_grid();
+        grid.transform.scale = Vector3::new(1.0, 0.0, 1.0);
+        grid.albedo = Color::new(2.0, 1.0, 1.0);
+        scene.volume_grids.push(grid);
+        assert_reported(&scene, Severity::Error, "volume_grids[0].transform.scale");
+        assert_reported(&scene, Severity::Warning, "volume_grids[0].albedo");
+    }
+
+    #[test]
+    fn progressive_and_denoise() {
+        let mut scene = scene();
+        scene.progressive = Some(Progressive {
+            samples: Some(0),
+            ..Progressive::default()
+        });
+        scene.denoise = Some(Denoiser {
+            strength: 2.0,
+            ..Denoiser::default()
+        });
+        assert_reported(&scene, Severity::Error, "progressive.samples");
+        assert_reported(&scene, Severity::Warning, "denoise.strength");
+    }
+
+    fn glass(index: RefractiveIndex, spectral: Option<Spectral>) -> Scene {
+        let mut material = Material::refractive(WHITE, 1.5, 1.0);
+        material.surface = SurfaceType::Refractive {
+            index,
+            transparency: 1.0,
+        };
+        let mut scene = lit_sphere(material).build();
+        scene.spectral = spectral;
+        scene
+    }
+
+    const INDEX: &str = "elements[0].Sphere.material.surface.Refractive.index";
+
+    fn bk7() -> RefractiveIndex {
+        RefractiveIndex::Dispersive(Dispersion::Sellmeier {
+            b: [1.0396, 0.2318, 1.0105],
+            c: [0.0060, 0.0200, 103.56],
+        })
+    }
+
+    #[test]
+    fn spectral_range() {
+        let scene = glass(
+            bk7(),
+            Some(Spectral {
+                min_wavelength: 0.0,
+                max_wavelength: 780.0,
+            }),
+        );
+        assert_reported(&scene, Severity::Error, "spectral.min_wavelength");
+        let scene = glass(
+            bk7(),
+            Some(Spectral {
+                min_wavelength: 780.0,
+                max_wavelength: 380.0,
+            }),
+        );
+        assert_reported(&scene, Severity::Error, "spectral.max_wavelength");
+        let diagnostics = glass(bk7(), Some(Spectral::default())).validate();
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn index_is_checked_over_the_scenes_range() {
+        // drops below 1 under 330 nm
+        let cauchy = RefractiveIndex::Dispersive(Dispersion::Cauchy { a: 1.05, b: -0.005 });
+        assert!(glass(cauchy, None).validate().is_empty());
+        assert!(glass(cauchy, Some(Spectral::default()))
+            .validate()
+            .is_empty());
+        let ultraviolet = Some(Spectral {
+            min_wavelength: 300.0,
+            max_wavelength: 780.0,
+        });
+        assert_reported(&glass(cauchy, ultraviolet), Severity::Error, INDEX);
+    }
+
+    #[test]
+    fn sellmeier_poles() {
+        // BK7's third term has its pole at about 10.2 µm
+        let infrared = Some(Spectral {
+            min_wavelength: 380.0,
+            max_wavelength: 12000.0,
+        });
+        let scene = glass(bk7(), infrared);
+        assert_reported(&scene, Severity::Error, INDEX);
+        assert!(scene.validate()[0].message.contains("pole at 10176"));
+        let visible_pole = RefractiveIndex::Dispersive(Dispersion::Sellmeier {
+            b: [1.0, 0.0, 0.0],
+            c: [0.25, 0.0, 0.0],
+        });
+        assert_reported(
+            &glass(visible_pole, Some(Spectral::default())),
+            Severity::Error,
+            INDEX,
+        );
+    }
+
+    #[test]
+    fn glowing_planes_are_not_sampled() {
+        let mut scene = lit_sphere(Material::diffuse(WHITE))
+            .plane(
+                Point::new(0.0, 5.0, 0.0),
+                Vector3::new(0.0, 1.0, 0.0),
+                Material::emissive(WHITE, 1.0),
+            )
+            .build();
+        assert!(scene.validate().is_empty());
+        scene.path_tracing = Some(Default::default());
+        assert_reported(
+            &scene,
+            Severity::Warning,
+            "elements[1].Plane.material.emission",
+        );
+    }
+}
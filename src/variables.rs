@@ -0,0 +1,316 @@
+//! Named values in scene files.
+//!
+//! The scene's `variables` map names values, and any string starting with `$` elsewhere
+//! in the scene is replaced before the scene is deserialized:
+//! - `"$floor_y"` is the value of `floor_y`, whatever its type, a number, a color or a whole material
+//! - `"$floor_y + 0.5"` or `"$(2 * radius)"` is arithmetic on numbers with `+ - * /` and parentheses,
+//!   inside it the `$` in front of names is optional
+//!
+//! Variables may use other variables, and can be overridden from the command line.
+use serde_json::{Map, Number, Value};
+
+/// Replaces every `$` expression in the scene and drops the `variables` map.
+/// `overrides` replace variables of the same name, they can't introduce new ones.
+pub fn resolve_variables(
+    scene: &mut Map<String, Value>,
+    overrides: &Map<String, Value>,
+) -> Result<(), String> {
+    let mut raw = match scene.remove("variables") {
+        Some(Value::Object(variables)) => variables,
+        Some(_) => return Err("variables: expected a map of names to values".to_string()),
+        None => Map::new(),
+    };
+    for (name, value) in overrides {
+        if !raw.contains_key(name) {
+            return Err(format!("the scene has no variable '{}' to set", name));
+        }
+        raw.insert(name.clone(), value.clone());
+    }
+
+    let mut variables = Variables {
+        raw: &raw,
+        resolved: Map::new(),
+        resolving: vec![],
+    };
+    for (key, value) in scene.iter_mut() {
+        variables.substitute(value, key)?;
+    }
+    Ok(())
+}
+
+struct Variables<'a> {
+    raw: &'a Map<String, Value>,
+    resolved: Map<String, Value>,
+    /// Variables being resolved right now, to catch ones that refer to themselves
+    resolving: Vec<String>,
+}
+
+impl Variables<'_> {
+    fn get(&mut self, name: &str) -> Result<Value, String> {
+        if let Some(value) = self.resolved.get(name) {
+            return Ok(value.clone());
+        }
+        let mut value = self
+            .raw
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("unknown variable '{}'", name))?;
+        if self.resolving.iter().any(|n| n == name) {
+            return Err(format!("variable '{}' refers to itself", name));
+        }
+        self.resolving.push(name.to_string());
+        self.substitute(&mut value, &format!("variables.{}", name))?;
+        self.resolving.pop();
+        self.resolved.insert(name.to_string(), value.clone());
+        Ok(value)
+    }
+
+    fn substitute(&mut self, value: &mut Value, path: &str) -> Result<(), String> {
+        match value {
+            Value::String(s) if s.starts_with('$') => {
+                *value = self
+                    .evaluate(&s.clone())
+                    .map_err(|err| format!("{}: {}", path, err))?
+            }
+            Value::Array(values) => {
+                for (i, value) in values.iter_mut().enumerate() {
+                    self.substitute(value, &format!("{}[{}]", path, i))?;
+                }
+            }
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    self.substitute(value, &format!("{}.{}", path, key))?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn evaluate(&mut self, expression: &str) -> Result<Value, String> {
+        let name = &expression[1..];
+        if !name.is_empty() && name.chars().all(is_name_char) {
+            return self.get(name);
+        }
+        let mut parser = Parser {
+            chars: expression.chars().collect(),
+            pos: 0,
+            variables: self,
+        };
+        let result = parser.expression()?;
+        parser.skip_spaces();
+        if parser.pos < parser.chars.len() {
+            return Err(format!(
+                "unexpected '{}' in '{}'",
+                parser.chars[parser.pos], expression
+            ));
+        }
+        number(result).ok_or_else(|| format!("'{}' is not a finite number", expression))
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Whole results stay integers, so they still fit fields like `width`
+fn number(v: f64) -> Option<Value> {
+    if v.fract() == 0.0 && v.abs() < i64::MAX as f64 {
+        Some(Value::from(v as i64))
+    } else {
+        Number::from_f64(v).map(Value::Number)
+    }
+}
+
+/// Recursive descent over `+ - * /`, parentheses, numbers and variable names
+struct Parser<'v, 'a> {
+    chars: Vec<char>,
+    pos: usize,
+    variables: &'v mut Variables<'a>,
+}
+
+impl Parser<'_, '_> {
+    fn skip_spaces(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn next_is(&mut self, c: char) -> bool {
+        self.skip_spaces();
+        if self.chars.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The optional `$` in front of a name, a number or `(`, a `$` anywhere else is left
+    /// for the caller to report
+    fn skip_dollar(&mut self) {
+        self.skip_spaces();
+        let next = self.chars.get(self.pos + 1);
+        if self.chars.get(self.pos) == Some(&'$')
+            && next.is_some_and(|c| is_name_char(*c) || *c == '(' || *c == '.')
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expression(&mut self) -> Result<f64, String> {
+        let mut value = self.term()?;
+        loop {
+            if self.next_is('+') {
+                value += self.term()?;
+            } else if self.next_is('-') {
+                value -= self.term()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<f64, String> {
+        let mut value = self.factor()?;
+        loop {
+            if self.next_is('*') {
+                value *= self.factor()?;
+            } else if self.next_is('/') {
+                value /= self.factor()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn factor(&mut self) -> Result<f64, String> {
+        if self.next_is('-') {
+            return Ok(-self.factor()?);
+        }
+        self.skip_dollar();
+        if self.next_is('(') {
+            let value = self.expression()?;
+            if !self.next_is(')') {
+                return Err("missing ')'".to_string());
+            }
+            return Ok(value);
+        }
+        self.skip_dollar();
+        if self
+            .chars
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_alphabetic() || *c == '_')
+        {
+            let name = self.take_while(is_name_char);
+            return match self.variables.get(&name)? {
+                Value::Number(n) => Ok(n.as_f64().unwrap_or_default()),
+                other => Err(format!("variable '{}' is not a number: {}", name, other)),
+            };
+        }
+        let literal = self.take_while(|c| c.is_ascii_digit() || c == '.');
+        literal.parse().map_err(|_| match self.chars.get(self.pos) {
+            Some(c) if literal.is_empty() => format!("unexpected '{}'", c),
+            _ if literal.is_empty() => "unexpected end of expression".to_string(),
+            _ => format!("'{}' is not a number", literal),
+        })
+    }
+
+    fn take_while(&mut self, accept: impl Fn(char) -> bool) -> String {
+        self.skip_spaces();
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(|c| accept(*c)) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The value of `"$..."` expression in a scene with `radius` 2 and `name` "glass"
+    fn evaluate(expression: &str) -> Result<Value, String> {
+        let mut scene = json!({
+            "variables": {"radius": 2, "name": "glass"},
+            "value": expression,
+        });
+        let scene = scene.as_object_mut().unwrap();
+        resolve_variables(scene, &Map::new())?;
+        Ok(scene["value"].clone())
+    }
+
+    #[test]
+    fn whole_values() {
+        assert_eq!(evaluate("$radius"), Ok(json!(2)));
+        assert_eq!(evaluate("$name"), Ok(json!("glass")));
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate("$1 + 2 * 3"), Ok(json!(7)));
+        assert_eq!(evaluate("$(1 + 2) * 3"), Ok(json!(9)));
+        assert_eq!(evaluate("$8 / 2 / 2"), Ok(json!(2)));
+        assert_eq!(evaluate("$radius * 0.25 + 1"), Ok(json!(1.5)));
+        assert_eq!(evaluate("$(2 * $radius)"), Ok(json!(4)));
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eq!(evaluate("$(-radius)"), Ok(json!(-2)));
+        assert_eq!(evaluate("$3 - -radius"), Ok(json!(5)));
+        assert_eq!(evaluate("$2 * -(1 + 1)"), Ok(json!(-4)));
+    }
+
+    #[test]
+    fn unknown_variable() {
+        let err = evaluate("$radius + height").unwrap_err();
+        assert!(err.contains("unknown variable 'height'"), "{}", err);
+    }
+
+    #[test]
+    fn not_a_number() {
+        let err = evaluate("$name + 1").unwrap_err();
+        assert!(err.contains("variable 'name' is not a number"), "{}", err);
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        assert!(evaluate("$radius 2").is_err());
+        assert!(evaluate("$(radius))").is_err());
+        assert!(evaluate("$(radius").is_err());
+        assert!(evaluate("$radius +").is_err());
+    }
+
+    #[test]
+    fn stray_dollars() {
+        assert!(evaluate("$ 3").is_err());
+        assert!(evaluate("$$radius").is_err());
+        assert!(evaluate("$radius + $ 1").is_err());
+    }
+
+    #[test]
+    fn self_reference() {
+        let mut scene = json!({"variables": {"a": "$b", "b": "$a + 1"}, "value": "$a"});
+        let err = resolve_variables(scene.as_object_mut().unwrap(), &Map::new()).unwrap_err();
+        assert!(err.contains("refers to itself"), "{}", err);
+    }
+
+    #[test]
+    fn overrides() {
+        let mut scene = json!({"variables": {"radius": 2}, "value": "$radius * 2"});
+        let overrides = json!({"radius": 5});
+        let overrides = overrides.as_object().unwrap();
+        resolve_variables(scene.as_object_mut().unwrap(), overrides).unwrap();
+        assert_eq!(scene["value"], json!(10));
+
+        let unknown = json!({"height": 5});
+        let mut scene = json!({"variables": {"radius": 2}});
+        assert!(
+            resolve_variables(scene.as_object_mut().unwrap(), unknown.as_object().unwrap())
+                .is_err()
+        );
+    }
+}
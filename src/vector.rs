@@ -18,6 +18,10 @@ impl <'de>Vector3 {
         Vector3 { x: v, y: v, z: v }
     }
 
+    pub fn new(x: f64, y: f64, z: f64) -> Vector3 {
+        Vector3 { x, y, z }
+    }
+
     pub fn length(&self) -> f64 {
         self.norm().sqrt()
     }
@@ -0,0 +1,466 @@
+//! Participating media: fog everywhere, homogeneous media filling closed elements, and
+//! smoke read from voxel grids.
+//!
+//! A ray through a medium loses light to absorption and out-scattering (Beer's law) and
+//! picks up light the medium scatters towards it from the scene's lights. That light is
+//! gathered at points stepped along the ray, each with a shadow ray, so shadows of the
+//! elements show as shafts in the medium. Light scatters once, there are no bounces
+//! within a medium.
+//!
+//! Grids vary from voxel to voxel, so rays find where they collide with one by delta
+//! tracking: they take random steps as if the whole grid were as dense as its densest voxel,
+//! and keep each collision with the chance the density there has of the densest. A ray
+//! stops at a collision and gets the light scattered there.
+use crate::{
+    entities::{Color, Element, Light, SurfaceType},
+    grid::VolumeGrid,
+    point::Point,
+    rendering::{Intersectable, Ray, BLACK, WHITE},
+    sampling::Rng,
+    scene::Scene,
+    vector::Vector3,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use std::f64::consts::PI;
+
+/// Rays without an end stop gathering light once this little of it gets through
+const CUTOFF_TRANSMITTANCE: f64 = 1e-3;
+/// How far a shadow ray moves past the boundary of a medium before looking further
+pub const BOUNDARY_STEP: f64 = 1e-6;
+/// Media boundaries a shadow ray crosses at most, in case it gets stuck on one
+const MAX_BOUNDARIES: u32 = 16;
+
+/// Homogeneous medium, coefficients are per unit of distance and per channel
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Medium {
+    #[serde(default = "Medium::none")]
+    pub absorption: Color,
+    #[serde(default = "Medium::none")]
+    pub scattering: Color,
+    /// Henyey-Greenstein asymmetry between -1 and 1, positive scatters light onwards,
+    /// negative back where it came from, 0 evenly in every direction
+    #[serde(default)]
+    pub anisotropy: f64,
+}
+
+impl Medium {
+    fn none() -> Color {
+        BLACK
+    }
+
+    pub fn extinction(&self) -> Color {
+        self.absorption + self.scattering
+    }
+
+    fn scatters(&self) -> bool {
+        self.scattering.red > 0.0 || self.scattering.green > 0.0 || self.scattering.blue > 0.0
+    }
+
+    /// Share of the light scattered into a direction at angle `acos(cos_theta)` from where
+    /// it was going, integrates to 1 over the sphere
+    pub fn phase(&self, cos_theta: f64) -> f32 {
+        let g = self.anisotropy;
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        ((1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())) as f32
+    }
+}
+
+/// Exponential fog filling the whole scene. It dims what is seen through it, but not the
+/// light reaching surfaces, so directional lights still get through.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Fog {
+    /// How quickly things fade into `color`, per unit of distance
+    pub density: f32,
+    pub color: Color,
+    /// Part of the density that scatters light from the lights towards the viewer,
+    /// which shows shafts of light. 0 is plain distance fog.
+    #[serde(default)]
+    pub scattering: f32,
+    /// Henyey-Greenstein asymmetry of the scattered light
+    #[serde(default)]
+    pub anisotropy: f64,
+}
+
+impl Fog {
+    fn medium(&self) -> Medium {
+        let scattering = self.density * self.scattering;
+        Medium {
+            absorption: uniform(self.density - scattering),
+            scattering: uniform(scattering),
+            anisotropy: self.anisotropy,
+        }
+    }
+}
+
+/// How finely light is gathered along rays through media
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct Volumes {
+    /// Distance between the points light is gathered at
+    pub step_size: f64,
+    /// Long rays take longer steps rather than more of them
+    pub max_steps: u32,
+}
+
+impl Default for Volumes {
+    fn default() -> Volumes {
+        Volumes {
+            step_size: 0.1,
+            max_steps: 128,
+        }
+    }
+}
+
+/// What a stretch of a ray through media does to the light coming along it
+pub struct MediumSegment {
+    pub transmittance: Color,
+    /// Light the media sent along the ray on the way
+    pub inscattered: Color,
+}
+
+impl MediumSegment {
+    pub fn apply(&self, color: Color) -> Color {
+        color * self.transmittance + self.inscattered
+    }
+}
+
+/// A point on the ray before `distance`, telling which media the ray is in
+fn probe(ray: &Ray, distance: f64) -> Point {
+    let t = if distance.is_finite() {
+        distance / 2.0
+    } else {
+        1.0
+    };
+    ray.origin + ray.direction * t
+}
+
+fn uniform(value: f32) -> Color {
+    Color::new(value, value, value)
+}
+
+/// Beer's law, what gets through `distance` of a medium
+fn beer(extinction: Color, distance: f64) -> Color {
+    let channel = |c: f32| {
+        if c <= 0.0 {
+            1.0
+        } else {
+            (-(c as f64) * distance).exp() as f32
+        }
+    };
+    Color::new(
+        channel(extinction.red),
+        channel(extinction.green),
+        channel(extinction.blue),
+    )
+}
+
+impl Element {
+    /// Whether `point` is inside the element at `time`. The inside of a plane is the
+    /// half-space behind its visible side.
+    pub fn contains(&self, point: Point, time: f64) -> bool {
+        let point = point - self.offset_at(time);
+        match self {
+            Element::Sphere(s) => (point - s.center).norm() < s.radius * s.radius,
+            Element::Plane(p) => (point - p.origin).dot(&p.normal) > 0.0,
+        }
+    }
+}
+
+impl Scene {
+    /// Media of the elements around `point`
+    fn element_media(&self, point: Point, time: f64) -> impl Iterator<Item = Medium> + '_ {
+        self.elements
+            .iter()
+            .filter_map(move |e| match e.material().medium {
+                Some(medium) if e.contains(point, time) => Some(medium),
+                _ => None,
+            })
+    }
+
+    /// What the media between the ray's origin and `distance` do to light coming along it,
+    /// `None` without any media in the way
+    pub fn media_along(&self, ray: &Ray, distance: f64) -> Option<MediumSegment> {
+        let collision = if self.volume_grids.is_empty() {
+            None
+        } else {
+            let mut rng = ray_rng(ray);
+            self.volume_grids
+                .iter()
+                .filter_map(|grid| delta_track(grid, ray, distance, &mut rng).map(|t| (grid, t)))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+        };
+        match collision {
+            None => self.homogeneous_along(ray, distance),
+            Some((grid, t)) => {
+                let medium = Medium {
+                    absorption: BLACK,
+                    scattering: grid.albedo,
+                    anisotropy: grid.anisotropy,
+                };
+                let scattered =
+                    self.scattered_light(ray.origin + ray.direction * t, ray, &[medium]);
+                Some(MediumSegment {
+                    transmittance: BLACK,
+                    inscattered: self
+                        .homogeneous_along(ray, t)
+                        .map_or(scattered, |segment| segment.apply(scattered)),
+                })
+            }
+        }
+    }
+
+    /// Same as [`Scene::media_along`] for fog and the media of elements. The ray doesn't
+    /// cross any surface before `distance`, so those media are the same all along.
+    fn homogeneous_along(&self, ray: &Ray, distance: f64) -> Option<MediumSegment> {
+        let media: Vec<Medium> = self
+            .fog
+            .map(|fog| fog.medium())
+            .into_iter()
+            .chain(self.element_media(probe(ray, distance), ray.time))
+            .collect();
+        if media.is_empty() {
+            return None;
+        }
+
+        let extinction = media.iter().fold(BLACK, |sum, m| sum + m.extinction());
+        let settings = self.volumes.unwrap_or_default();
+        let length = if distance.is_finite() {
+            distance
+        } else {
+            let weakest = extinction.red.min(extinction.green).min(extinction.blue) as f64;
+            if weakest > 0.0 {
+                -CUTOFF_TRANSMITTANCE.ln() / weakest
+            } else {
+                settings.step_size * settings.max_steps as f64
+            }
+        };
+
+        let mut inscattered = BLACK;
+        if media.iter().any(Medium::scatters) && length > 0.0 {
+            let steps = ((length / settings.step_size).ceil() as u32).clamp(1, settings.max_steps);
+            let step = length / steps as f64;
+            for i in 0..steps {
+                let t = (i as f64 + 0.5) * step;
+                let point = ray.origin + ray.direction * t;
+                inscattered = inscattered
+                    + self.scattered_light(point, ray, &media) * beer(extinction, t) * step as f32;
+            }
+        }
+        if let Some(fog) = self.fog {
+            inscattered =
+                inscattered + fog.color * (1.0 - beer(uniform(fog.density), distance).red);
+        }
+        Some(MediumSegment {
+            transmittance: beer(extinction, distance),
+            inscattered,
+        })
+    }
+
+    /// Light the media at `point` scatter back along the ray, per unit of distance
+    fn scattered_light(&self, point: Point, ray: &Ray, media: &[Medium]) -> Color {
+        let sun = self.sun();
+        let mut color = BLACK;
+        for light in self.lights.iter().chain(&sun) {
+            let direction = light.direction_from(&point);
+            let reaching =
+                self.shadow_transmittance(point, direction, light.distance(&point), ray.time);
+            if reaching == BLACK {
+                continue;
+            }
+            let light_color = light.color_at(&point) * light.intensity(&point) * reaching;
+            let cos_theta = direction.dot(&ray.direction);
+            for medium in media {
+                color = color + medium.scattering * light_color * medium.phase(cos_theta);
+            }
+        }
+        color
+    }
+
+    /// How much of a light gets to a surface point, black in the shadow of an opaque element
+    pub fn light_transmittance(
+        &self,
+        light: &Light,
+        hit_point: Point,
+        surface_normal: Vector3,
+        time: f64,
+    ) -> Color {
+        if !self.effects.shadows {
+            return WHITE;
+        }
+        self.shadow_transmittance(
+            hit_point + (surface_normal * self.shadow_bias),
+            light.direction_from(&hit_point),
+            light.distance(&hit_point),
+            time,
+        )
+    }
+
+    /// Follows a shadow ray through the boundaries of media and through glass, which dim and
+    /// tint the light on the way, until it gets `distance` far or hits an opaque element.
+    /// Glass tints the light by its coloration and transparency once per element crossed,
+    /// however many of its surfaces the ray goes through, but doesn't bend it, so its shadows
+    /// fall where an opaque element's would.
+    pub fn shadow_transmittance(
+        &self,
+        mut origin: Point,
+        direction: Vector3,
+        mut distance: f64,
+        time: f64,
+    ) -> Color {
+        let mut transmittance = WHITE;
+        let mut tinted: Vec<&Element> = vec![];
+        for _ in 0..MAX_BOUNDARIES {
+            self.stats.shadow_ray();
+            let ray = Ray {
+                origin,
+                direction,
+                time,
+                wavelength: None,
+            };
+            let hit = self.trace(&ray).filter(|i| i.distance <= distance);
+            let reach = hit.as_ref().map_or(distance, |i| i.distance);
+            if self.elements.iter().any(|e| e.material().medium.is_some()) {
+                let extinction = self
+                    .element_media(probe(&ray, reach), time)
+                    .fold(BLACK, |sum, m| sum + m.extinction());
+                transmittance = transmittance * beer(extinction, reach);
+            }
+            transmittance = transmittance * self.grid_transmittance(&ray, reach);
+            let i = match hit {
+                None => return transmittance,
+                Some(i) => i,
+            };
+            let material = i.element.material();
+            match material.surface {
+                SurfaceType::Refractive { transparency, .. } if self.effects.refractions => {
+                    if !tinted.iter().any(|e| std::ptr::eq(*e, i.element)) {
+                        let hit_point = ray.origin + direction * i.distance;
+                        let coords = i.element.texture_coords(&i.local(hit_point));
+                        transmittance =
+                            transmittance * material.coloration.color(&coords) * transparency;
+                        if transmittance == BLACK {
+                            return BLACK;
+                        }
+                        tinted.push(i.element);
+                    }
+                }
+                // like camera rays, shadow rays only get into a medium through glass
+                _ => return BLACK,
+            }
+            origin = origin + direction * (i.distance + BOUNDARY_STEP);
+            distance -= i.distance + BOUNDARY_STEP;
+        }
+        BLACK
+    }
+
+    /// What gets through the voxel grids along a shadow ray, ray marched rather than
+    /// tracked so shadows in smoke come out smooth
+    fn grid_transmittance(&self, ray: &Ray, distance: f64) -> Color {
+        let settings = self.volumes.unwrap_or_default();
+        let mut optical_depth = 0.0;
+        for grid in &self.volume_grids {
+            let (origin, direction, near, far) = match grid.clip(ray, distance) {
+                Some(clipped) => clipped,
+                None => continue,
+            };
+            let length = far - near;
+            let steps = ((length / settings.step_size).ceil() as u32).clamp(1, settings.max_steps);
+            let step = length / steps as f64;
+            for i in 0..steps {
+                let t = near + (i as f64 + 0.5) * step;
+                optical_depth += grid.extinction_at(origin + direction * t) * step;
+            }
+        }
+        uniform((-optical_depth).exp() as f32)
+    }
+}
+
+/// Random numbers for one ray, the same every time it is traced
+fn ray_rng(ray: &Ray) -> Rng {
+    let bits = [
+        ray.origin.x,
+        ray.origin.y,
+        ray.origin.z,
+        ray.direction.x,
+        ray.direction.y,
+        ray.direction.z,
+        ray.time,
+    ]
+    .iter()
+    .fold(0xcbf2_9ce4_8422_2325_u64, |hash, v| {
+        (hash ^ v.to_bits()).wrapping_mul(0x0100_0000_01b3)
+    });
+    Rng::new(bits)
+}
+
+/// Distance along the ray to where it collides with the grid before `distance`, if it does
+fn delta_track(grid: &VolumeGrid, ray: &Ray, distance: f64, rng: &mut Rng) -> Option<f64> {
+    let majorant = grid.majorant();
+    if majorant <= 0.0 {
+        return None;
+    }
+    let (origin, direction, near, far) = grid.clip(ray, distance)?;
+    let mut t = near;
+    loop {
+        t -= (1.0 - rng.next_f64()).ln() / majorant;
+        if t >= far {
+            return None;
+        }
+        if rng.next_f64() * majorant < grid.extinction_at(origin + direction * t) {
+            return Some(t);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::lit_sphere, entities::Material};
+
+    fn medium() -> Medium {
+        Medium {
+            absorption: uniform(0.5),
+            scattering: BLACK,
+            anisotropy: 0.0,
+        }
+    }
+
+    /// What reaches the point right under the test sphere of `material`
+    fn under_sphere(material: Material) -> Color {
+        let scene = lit_sphere(material).build();
+        scene.light_transmittance(
+            &scene.lights[0],
+            Point::new(0.0, -2.0, -5.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            0.0,
+        )
+    }
+
+    #[test]
+    fn opaque_elements_with_a_medium_cast_shadows() {
+        let material = Material {
+            medium: Some(medium()),
+            ..Material::diffuse(WHITE)
+        };
+        assert_eq!(under_sphere(material), BLACK);
+    }
+
+    #[test]
+    fn glass_tints_once_per_element() {
+        let reaching = under_sphere(Material::refractive(WHITE, 1.5, 0.5));
+        assert!((reaching.red - 0.5).abs() < 1e-6, "{:?}", reaching);
+    }
+
+    #[test]
+    fn glass_media_dim_shadow_rays() {
+        let material = Material {
+            medium: Some(medium()),
+            ..Material::refractive(WHITE, 1.0, 1.0)
+        };
+        // two units through the medium
+        let expected = (-1.0f32).exp();
+        let reaching = under_sphere(material);
+        assert!((reaching.red - expected).abs() < 1e-4, "{:?}", reaching);
+    }
+}
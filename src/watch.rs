@@ -0,0 +1,96 @@
+//! Watch mode: renders again whenever the scene file or one of its textures changes.
+//!
+//! Files are polled for their modification time, which works the same for editors that
+//! write in place and the ones that save to a temporary file and rename it.
+use crate::{
+    loader::{load_scene, read_scene_source, LoadedScene},
+    stats::PhaseTimer,
+    utils::print_green,
+};
+use serde_json::{Map, Value};
+use termion::color;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Editors may touch a file several times while saving, let them finish first
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Files to watch with their modification times when last looked at
+struct Watched(Vec<(PathBuf, Option<SystemTime>)>);
+
+impl Watched {
+    fn new(paths: Vec<PathBuf>) -> Watched {
+        Watched(
+            paths
+                .into_iter()
+                .map(|path| {
+                    let time = modified(&path);
+                    (path, time)
+                })
+                .collect(),
+        )
+    }
+
+    fn changed(&self) -> Option<&Path> {
+        self.0
+            .iter()
+            .find(|(path, time)| modified(path) != *time)
+            .map(|(path, _)| path.as_path())
+    }
+}
+
+/// Loads and renders the scene, then again after every change until the process is killed.
+/// A scene that fails to load or render is reported and the last good image stays where it is.
+pub fn watch<F: FnMut(LoadedScene, PhaseTimer) -> Result<(), String>>(
+    scene_path: &Path,
+    variables: &Map<String, Value>,
+    mut render: F,
+) {
+    let mut files = vec![scene_path.to_path_buf()];
+    loop {
+        let mut timer = PhaseTimer::default();
+        // dependencies are only known once the scene is readable, keep the old ones until then
+        if let Ok(source) = read_scene_source(scene_path, variables) {
+            files = source.dependencies();
+        }
+        let watched = Watched::new(files.clone());
+
+        let rendered = load_scene(scene_path, variables)
+            .map_err(|err| err.to_string())
+            .and_then(|loaded| {
+                timer.end("load");
+                for warning in &loaded.warnings {
+                    println!("{}", warning);
+                }
+                render(loaded, timer)
+            });
+        if let Err(err) = rendered {
+            println!(
+                "{}{}{}\nkeeping the last good image",
+                color::Fg(color::Red),
+                err,
+                color::Fg(color::Reset)
+            );
+        }
+
+        println!("watching {} files, Ctrl-C to stop", files.len());
+        let changed = loop {
+            thread::sleep(POLL_INTERVAL);
+            if let Some(path) = watched.changed() {
+                break path.to_path_buf();
+            }
+        };
+        thread::sleep(SETTLE_TIME);
+        print_green(&format!("\n{} changed, rendering again", changed.display()));
+    }
+}